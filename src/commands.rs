@@ -0,0 +1,386 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use bson::{doc, Document};
+use futures_util::stream::StreamExt;
+use mongodb::{Client, Collection};
+
+use crate::{
+    models::{Accounts, LikeCollection, SkinCollection, SkinMeta, TextureKind},
+    signing::start_key_rotation,
+    texture::{is_legacy, is_texture_hash, perceptual_hash, phash_bands, phash_distance, texture_hash, upgrade_legacy_skin},
+    util::{get_phash_threshold, get_skins_path, offline_uuid},
+};
+
+// Converts every stored 64x32 skin to the 64x64 layout, keeping the original file as `{id}.legacy.png`.
+pub async fn convert_legacy_skins(client: &Client) -> std::io::Result<()> {
+    let collection: Collection<SkinCollection> = client.database("ouja_skins").collection("skins");
+    let skins_path = get_skins_path();
+    let mut cursor = collection
+        .find(doc! { "kind": TextureKind::Skin.filter(), "metadata.Image.legacy": { "$ne": true } }, None)
+        .await
+        .expect("failed to query skins");
+
+    let mut converted = 0;
+    while let Some(skin) = cursor.next().await {
+        let skin = match skin {
+            Ok(skin) => skin,
+            Err(err) => {
+                println!("{:?} - collecting skins", err);
+                continue;
+            }
+        };
+        let (width, height) = match skin.metadata {
+            SkinMeta::Image { width, height, .. } => (width, height),
+            SkinMeta::Cape { .. } => continue,
+        };
+        if !is_legacy(width as u32, height as u32) {
+            continue;
+        }
+
+        let path = format!("{}/{}.png", skins_path, skin.id);
+        let buffer = match fs::read(&path) {
+            Ok(buffer) => buffer,
+            Err(err) => {
+                println!("{} - reading {}", err, path);
+                continue;
+            }
+        };
+        let texture = match image::load_from_memory(&buffer) {
+            Ok(texture) => texture.to_rgba8(),
+            Err(err) => {
+                println!("{} - decoding {}", err, path);
+                continue;
+            }
+        };
+        let upgraded = match upgrade_legacy_skin(&buffer) {
+            Ok(upgraded) => upgraded,
+            Err(err) => {
+                println!("{} - converting skin {}", err, skin.id);
+                continue;
+            }
+        };
+        let legacy_path = format!("{}/{}.legacy.png", skins_path, skin.id);
+        if let Err(err) = fs::write(&legacy_path, &buffer).and_then(|_| fs::write(&path, &upgraded)) {
+            println!("{} - storing converted skin {}", err, skin.id);
+            continue;
+        }
+
+        // Same fields an upload of the legacy skin stores: the hash over the uploaded pixels,
+        // the size of the converted file and the perceptual hash of its 64x64 layout.
+        // The history entry of the current revision is kept in step so restoring it later matches.
+        let phash = perceptual_hash(&texture, TextureKind::Skin);
+        let hash = texture_hash(&texture);
+        let mut filter = doc! { "id": &skin.id };
+        let mut fields = doc! {
+            "hash": &hash,
+            "size": upgraded.len() as i64,
+            "phash_bands": phash_bands(&phash),
+            "phash": &phash,
+            "metadata.Image.height": width as i64,
+            "metadata.Image.content_type": "image/png",
+            "metadata.Image.legacy": true,
+        };
+        if skin.revisions.iter().any(|revision| revision.revision == skin.revision) {
+            filter.insert("revisions.revision", skin.revision);
+            fields.insert("revisions.$.hash", &hash);
+            fields.insert("revisions.$.size", upgraded.len() as i64);
+            fields.insert("revisions.$.phash", &phash);
+            fields.insert("revisions.$.metadata.Image.height", width as i64);
+            fields.insert("revisions.$.metadata.Image.content_type", "image/png");
+            fields.insert("revisions.$.metadata.Image.legacy", true);
+        }
+        match collection.update_one(filter, doc! { "$set": fields }, None).await {
+            Ok(_result) => converted += 1,
+            Err(err) => println!("{:?} - updating skin {}", err, skin.id),
+        }
+    }
+
+    println!("Converted {} legacy skins", converted);
+    Ok(())
+}
+
+fn stored_phash(path: &str, kind: TextureKind) -> Option<String> {
+    let buffer = match fs::read(path) {
+        Ok(buffer) => buffer,
+        Err(err) => {
+            println!("{} - reading {}", err, path);
+            return None;
+        }
+    };
+    match image::load_from_memory(&buffer) {
+        Ok(texture) => Some(perceptual_hash(&texture.to_rgba8(), kind)),
+        Err(err) => {
+            println!("{} - decoding {}", err, path);
+            None
+        }
+    }
+}
+
+// Recomputes the perceptual hash of every texture and its revisions, for textures uploaded
+// before near duplicate detection existed and whenever the hash changes. The near duplicate
+// flags are then checked again against the new hashes, each texture against older uploads
+// of other owners like on upload.
+pub async fn backfill_phash(client: &Client) -> std::io::Result<()> {
+    let collection: Collection<SkinCollection> = client.database("ouja_skins").collection("skins");
+    let skins_path = get_skins_path();
+    let mut cursor = collection.find(None, None).await.expect("failed to query skins");
+
+    let mut hashed = 0;
+    while let Some(skin) = cursor.next().await {
+        let skin = match skin {
+            Ok(skin) => skin,
+            Err(err) => {
+                println!("{:?} - collecting skins", err);
+                continue;
+            }
+        };
+
+        let Some(phash) = stored_phash(&format!("{}/{}.png", skins_path, skin.id), skin.kind) else {
+            continue;
+        };
+        let mut revisions = skin.revisions.clone();
+        for revision in revisions.iter_mut() {
+            if revision.revision == skin.revision {
+                revision.phash = Some(phash.clone());
+            } else if let Some(revision_phash) = stored_phash(&format!("{}/{}.r{}.png", skins_path, skin.id, revision.revision), skin.kind) {
+                revision.phash = Some(revision_phash);
+            }
+        }
+        let revisions = match bson::to_bson(&revisions) {
+            Ok(revisions) => revisions,
+            Err(err) => {
+                println!("{:?} - serializing revisions of {}", err, skin.id);
+                continue;
+            }
+        };
+
+        match collection
+            .update_one(
+                doc! { "id": &skin.id },
+                doc! { "$set": { "phash_bands": phash_bands(&phash), "phash": phash, "revisions": revisions } },
+                None,
+            )
+            .await
+        {
+            Ok(_result) => hashed += 1,
+            Err(err) => println!("{:?} - updating skin {}", err, skin.id),
+        }
+    }
+    println!("Hashed {} textures", hashed);
+
+    let threshold = get_phash_threshold();
+    let mut cursor = collection.find(doc! { "phash": { "$ne": null } }, None).await.expect("failed to query skins");
+    let mut flagged = 0;
+    while let Some(skin) = cursor.next().await {
+        let skin = match skin {
+            Ok(skin) => skin,
+            Err(err) => {
+                println!("{:?} - collecting skins", err);
+                continue;
+            }
+        };
+        let Some(phash) = &skin.phash else {
+            continue;
+        };
+
+        let filter = doc! {
+            "kind": skin.kind.filter(),
+            "owner": { "$ne": &skin.owner },
+            "date": { "$lt": skin.date },
+            "phash_bands": { "$in": phash_bands(phash) },
+        };
+        let mut candidates = match collection.find(filter, None).await {
+            Ok(candidates) => candidates,
+            Err(err) => {
+                println!("{:?} - finding copies of {}", err, skin.id);
+                continue;
+            }
+        };
+        let mut suspected_copy_of: Vec<String> = Vec::new();
+        while let Some(Ok(candidate)) = candidates.next().await {
+            let distance = candidate.phash.as_deref().and_then(|other| phash_distance(phash, other));
+            if distance.is_some_and(|distance| distance <= threshold) {
+                suspected_copy_of.push(candidate.id);
+            }
+        }
+
+        if !suspected_copy_of.is_empty() {
+            flagged += 1;
+        }
+        if let Err(err) = collection.update_one(doc! { "id": &skin.id }, doc! { "$set": { "suspected_copy_of": suspected_copy_of } }, None).await {
+            println!("{:?} - updating skin {}", err, skin.id);
+        }
+    }
+    println!("Flagged {} textures as near duplicates", flagged);
+    Ok(())
+}
+
+// Hashes the texture stored at `base` the way it was uploaded, a converted legacy skin
+// keeps its uploaded layout in `.legacy.png`.
+fn stored_texture_hash(base: &str) -> Option<String> {
+    let legacy = format!("{}.legacy.png", base);
+    let path = if Path::new(&legacy).exists() { legacy } else { format!("{}.png", base) };
+    let buffer = match fs::read(&path) {
+        Ok(buffer) => buffer,
+        Err(err) => {
+            println!("{} - reading {}", err, path);
+            return None;
+        }
+    };
+    match image::load_from_memory(&buffer) {
+        Ok(texture) => Some(texture_hash(&texture.to_rgba8())),
+        Err(err) => {
+            println!("{} - decoding {}", err, path);
+            None
+        }
+    }
+}
+
+// Replaces the base64 hashes of textures stored before hashes were taken over the pixels,
+// they can hold `/` and `+` and break texture URLs. Revisions are rehashed as well so
+// restoring one does not bring an old hash back.
+pub async fn backfill_hashes(client: &Client) -> std::io::Result<()> {
+    let collection: Collection<SkinCollection> = client.database("ouja_skins").collection("skins");
+    let skins_path = get_skins_path();
+    let mut cursor = collection.find(None, None).await.expect("failed to query skins");
+
+    let mut rehashed = 0;
+    while let Some(skin) = cursor.next().await {
+        let skin = match skin {
+            Ok(skin) => skin,
+            Err(err) => {
+                println!("{:?} - collecting skins", err);
+                continue;
+            }
+        };
+        if is_texture_hash(&skin.hash) && skin.revisions.iter().all(|revision| is_texture_hash(&revision.hash)) {
+            continue;
+        }
+
+        let Some(hash) = stored_texture_hash(&format!("{}/{}", skins_path, skin.id)) else {
+            continue;
+        };
+        let mut revisions = skin.revisions.clone();
+        for revision in revisions.iter_mut() {
+            if revision.revision == skin.revision {
+                revision.hash = hash.clone();
+            } else if !is_texture_hash(&revision.hash) {
+                if let Some(revision_hash) = stored_texture_hash(&format!("{}/{}.r{}", skins_path, skin.id, revision.revision)) {
+                    revision.hash = revision_hash;
+                }
+            }
+        }
+        let revisions = match bson::to_bson(&revisions) {
+            Ok(revisions) => revisions,
+            Err(err) => {
+                println!("{:?} - serializing revisions of {}", err, skin.id);
+                continue;
+            }
+        };
+
+        match collection.update_one(doc! { "id": &skin.id }, doc! { "$set": { "hash": &hash, "revisions": revisions } }, None).await {
+            Ok(_result) => rehashed += 1,
+            Err(err) => println!("{:?} - updating skin {}", err, skin.id),
+        }
+    }
+
+    println!("Rehashed {} textures", rehashed);
+    Ok(())
+}
+
+// Drops likes left behind by accounts or skins removed outside of the API and
+// recomputes every like counter from the likes that remain.
+pub async fn recount_likes(client: &Client) -> std::io::Result<()> {
+    let database = client.database("ouja_skins");
+    let skins: Collection<SkinCollection> = database.collection("skins");
+    let likes: Collection<LikeCollection> = database.collection("likes");
+
+    let mut removed = 0;
+    for (field, collection) in [("account", "accounts"), ("skin", "skins")] {
+        let ids = likes.distinct(field, None, None).await.expect("failed to query likes");
+        let existing = database
+            .collection::<Document>(collection)
+            .distinct("id", doc! { "id": { "$in": &ids } }, None)
+            .await
+            .expect("failed to query owners of likes");
+        let missing: Vec<_> = ids.into_iter().filter(|id| !existing.contains(id)).collect();
+        if !missing.is_empty() {
+            match likes.delete_many(doc! { field: { "$in": missing } }, None).await {
+                Ok(result) => removed += result.deleted_count,
+                Err(err) => println!("{:?} - removing likes", err),
+            }
+        }
+    }
+
+    let mut counts: HashMap<String, i64> = HashMap::new();
+    let mut cursor = likes
+        .aggregate([doc! { "$group": { "_id": "$skin", "count": { "$sum": 1 } } }], None)
+        .await
+        .expect("failed to count likes");
+    while let Some(count) = cursor.next().await {
+        match count {
+            Ok(count) => {
+                if let (Ok(skin), Ok(count)) = (count.get_str("_id"), count.get_i32("count")) {
+                    counts.insert(skin.to_string(), count as i64);
+                }
+            },
+            Err(err) => println!("{:?} - counting likes", err),
+        }
+    }
+
+    let mut cursor = skins.find(doc! { "kind": TextureKind::Skin.filter() }, None).await.expect("failed to query skins");
+    let mut corrected = 0;
+    while let Some(skin) = cursor.next().await {
+        let skin = match skin {
+            Ok(skin) => skin,
+            Err(err) => {
+                println!("{:?} - collecting skins", err);
+                continue;
+            }
+        };
+        let count = counts.get(&skin.id).copied().unwrap_or(0);
+        if count == skin.likes {
+            continue;
+        }
+        match skins.update_one(doc! { "id": &skin.id }, doc! { "$set": { "likes": count } }, None).await {
+            Ok(_result) => corrected += 1,
+            Err(err) => println!("{:?} - updating skin {}", err, skin.id),
+        }
+    }
+
+    println!("Removed {} orphaned likes, corrected {} like counters", removed, corrected);
+    Ok(())
+}
+
+// Stores the offline mode UUID of accounts registered before lookups by it existed.
+pub async fn backfill_offline_uuids(client: &Client) -> std::io::Result<()> {
+    let accounts: Collection<Accounts> = client.database("ouja_skins").collection("accounts");
+    let mut cursor = accounts.find(doc! { "offline_uuid": null }, None).await.expect("failed to query accounts");
+
+    let mut updated = 0;
+    while let Some(account) = cursor.next().await {
+        let account = match account {
+            Ok(account) => account,
+            Err(err) => {
+                println!("{:?} - collecting accounts", err);
+                continue;
+            }
+        };
+        match accounts.update_one(doc! { "id": &account.id }, doc! { "$set": { "offline_uuid": offline_uuid(&account.username) } }, None).await {
+            Ok(_result) => updated += 1,
+            Err(err) => println!("{:?} - updating account {}", err, account.id),
+        }
+    }
+
+    println!("Stored the offline UUID of {} accounts", updated);
+    Ok(())
+}
+
+// Starts a textures signing key rotation, see `start_key_rotation` for the schedule.
+pub async fn rotate_signing_key(client: &Client) -> std::io::Result<()> {
+    match start_key_rotation(client).await {
+        Ok(key) => println!("Generated signing key {}, it signs from {}", key.id, key.activates),
+        Err(err) => println!("{} - rotating signing key", err),
+    }
+    Ok(())
+}
@@ -0,0 +1,85 @@
+use bson::DateTime;
+use serde::{Deserialize, Serialize};
+
+// Deepest offset a cursor can point at, further than anyone pages through search results.
+const MAX_OFFSET: u64 = 100_000;
+
+// Position of the last item of a page, handed to clients as an opaque string.
+// Listings sorted by date continue after the last (date, id) pair, the other
+// sort orders have no stable key to continue from and use an offset.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(tag = "k", rename_all = "lowercase")]
+pub enum Cursor {
+    After { sort: String, date: i64, id: String },
+    Offset { sort: String, offset: u64 },
+}
+
+impl Cursor {
+    pub fn after(sort: &str, date: DateTime, id: &str) -> Cursor {
+        Cursor::After {
+            sort: sort.to_string(),
+            date: date.timestamp_millis(),
+            id: id.to_string(),
+        }
+    }
+
+    pub fn sort(&self) -> &str {
+        match self {
+            Cursor::After { sort, .. } | Cursor::Offset { sort, .. } => sort,
+        }
+    }
+
+    pub fn encode(&self) -> String {
+        base64::encode_config(serde_json::to_vec(self).unwrap_or_default(), base64::URL_SAFE_NO_PAD)
+    }
+
+    // Offsets past `MAX_OFFSET` are refused, no page the API hands out gets there and
+    // clients can put any number in a cursor.
+    pub fn decode(cursor: &str) -> Option<Cursor> {
+        let bytes = base64::decode_config(cursor, base64::URL_SAFE_NO_PAD).ok()?;
+        match serde_json::from_slice(&bytes).ok()? {
+            Cursor::Offset { offset, .. } if offset > MAX_OFFSET => None,
+            cursor => Some(cursor),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encoded(json: &str) -> String {
+        base64::encode_config(json, base64::URL_SAFE_NO_PAD)
+    }
+
+    #[test]
+    fn decodes_what_it_encodes() {
+        let after = Cursor::after("newest", DateTime::from_millis(1_700_000_000_000), "6f1c-ab/+");
+        assert_eq!(Cursor::decode(&after.encode()), Some(after));
+        let offset = Cursor::Offset { sort: "popular".to_string(), offset: 48 };
+        assert_eq!(Cursor::decode(&offset.encode()), Some(offset));
+    }
+
+    #[test]
+    fn encodes_to_url_safe_text() {
+        let cursor = Cursor::after("newest", DateTime::from_millis(0), "??>>??").encode();
+        assert!(cursor.bytes().all(|byte| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_'));
+    }
+
+    #[test]
+    fn rejects_malformed_cursors() {
+        assert_eq!(Cursor::decode(""), None);
+        assert_eq!(Cursor::decode("not base64!"), None);
+        assert_eq!(Cursor::decode(&encoded("not json")), None);
+        assert_eq!(Cursor::decode(&encoded(r#"{"k":"sideways","sort":"newest"}"#)), None);
+        assert_eq!(Cursor::decode(&encoded(r#"{"k":"offset","sort":"popular","offset":-1}"#)), None);
+    }
+
+    #[test]
+    fn refuses_offsets_past_the_maximum() {
+        let deepest = Cursor::Offset { sort: "popular".to_string(), offset: MAX_OFFSET };
+        assert_eq!(Cursor::decode(&deepest.encode()), Some(deepest));
+        let deeper = Cursor::Offset { sort: "popular".to_string(), offset: MAX_OFFSET + 1 };
+        assert_eq!(Cursor::decode(&deeper.encode()), None);
+    }
+}
@@ -0,0 +1,106 @@
+use bson::{doc, Document};
+use mongodb::{options::IndexOptions, Client, Collection, IndexModel};
+
+use std::time::Duration;
+
+use crate::models::{Accounts, BlockCollection, CommentCollection, MinecraftClaim, CuratedCollection, FollowCollection, NotificationCollection, LikeCollection, ReportCollection, SkinCollection, StatBucket, SigningKey, StatHit, YggdrasilJoin, YggdrasilToken};
+
+// Creates one index. A failure is logged and does not keep the other indexes from being created.
+async fn create_index<T>(collection: &Collection<T>, keys: Document, options: IndexOptions) {
+    let name = options.name.clone().unwrap_or_default();
+    let index = IndexModel::builder().keys(keys).options(options).build();
+    if let Err(err) = collection.create_index(index, None).await {
+        println!("{:?} - creating index {} on {}", err, name, collection.name());
+    }
+}
+
+pub async fn ensure_indexes(client: &Client) {
+    let skins = client.database("ouja_skins").collection::<SkinCollection>("skins");
+    create_index(&skins, doc! { "phash_bands": 1 }, IndexOptions::builder().name("phash_bands".to_string()).build()).await;
+    create_index(&skins, doc! { "tags": 1, "date": -1 }, IndexOptions::builder().name("tags".to_string()).build()).await;
+    create_index(
+        &skins,
+        doc! { "title": "text", "tags": "text", "description": "text" },
+        IndexOptions::builder()
+            .name("search".to_string())
+            .weights(doc! { "title": 10, "tags": 5, "description": 1 })
+            .build(),
+    )
+    .await;
+    create_index(&skins, doc! { "date": -1, "id": -1 }, IndexOptions::builder().name("newest".to_string()).build()).await;
+    create_index(&skins, doc! { "owner": 1, "date": -1, "id": -1 }, IndexOptions::builder().name("owner".to_string()).build()).await;
+    create_index(&skins, doc! { "hash": 1 }, IndexOptions::builder().name("hash".to_string()).build()).await;
+    create_index(&skins, doc! { "likes": -1, "date": -1, "id": -1 }, IndexOptions::builder().name("popular".to_string()).build()).await;
+    create_index(&skins, doc! { "trending": -1, "date": -1, "id": -1 }, IndexOptions::builder().name("trending".to_string()).build()).await;
+
+    let accounts = client.database("ouja_skins").collection::<Accounts>("accounts");
+    create_index(&accounts, doc! { "offline_uuid": 1 }, IndexOptions::builder().name("offline_uuid".to_string()).build()).await;
+    // A Minecraft identity belongs to one account at most.
+    create_index(
+        &accounts,
+        doc! { "minecraft.uuid": 1 },
+        IndexOptions::builder()
+            .name("minecraft_uuid".to_string())
+            .unique(true)
+            .partial_filter_expression(doc! { "minecraft.uuid": { "$exists": true } })
+            .build(),
+    )
+    .await;
+    create_index(&accounts, doc! { "minecraft.name": 1 }, IndexOptions::builder().name("minecraft_name".to_string()).build()).await;
+
+    let claims = client.database("ouja_skins").collection::<MinecraftClaim>("minecraft_claims");
+    create_index(&claims, doc! { "account": 1 }, IndexOptions::builder().name("account".to_string()).unique(true).build()).await;
+    create_index(&claims, doc! { "expires": 1 }, IndexOptions::builder().name("expires".to_string()).expire_after(Duration::ZERO).build()).await;
+
+    let likes = client.database("ouja_skins").collection::<LikeCollection>("likes");
+    create_index(&likes, doc! { "account": 1, "skin": 1 }, IndexOptions::builder().name("account_skin".to_string()).unique(true).build()).await;
+    create_index(&likes, doc! { "account": 1, "date": -1, "skin": -1 }, IndexOptions::builder().name("account_date".to_string()).build()).await;
+    create_index(&likes, doc! { "skin": 1 }, IndexOptions::builder().name("skin".to_string()).build()).await;
+
+    let stats = client.database("ouja_skins").collection::<StatBucket>("stats");
+    create_index(&stats, doc! { "skin": 1, "event": 1, "bucket": 1 }, IndexOptions::builder().name("skin_event_bucket".to_string()).unique(true).build()).await;
+    create_index(&stats, doc! { "bucket": 1 }, IndexOptions::builder().name("bucket".to_string()).build()).await;
+
+    let hits = client.database("ouja_skins").collection::<StatHit>("stat_hits");
+    create_index(&hits, doc! { "skin": 1, "event": 1, "client": 1 }, IndexOptions::builder().name("skin_event_client".to_string()).unique(true).build()).await;
+    create_index(&hits, doc! { "expires": 1 }, IndexOptions::builder().name("expires".to_string()).expire_after(Duration::ZERO).build()).await;
+
+    let comments = client.database("ouja_skins").collection::<CommentCollection>("comments");
+    create_index(&comments, doc! { "skin": 1, "root": 1, "date": 1, "id": 1 }, IndexOptions::builder().name("skin_thread".to_string()).build()).await;
+    create_index(&comments, doc! { "parent": 1 }, IndexOptions::builder().name("parent".to_string()).build()).await;
+
+    let reports = client.database("ouja_skins").collection::<ReportCollection>("reports");
+    create_index(&reports, doc! { "kind": 1, "target": 1, "reporter": 1 }, IndexOptions::builder().name("kind_target_reporter".to_string()).unique(true).build()).await;
+    create_index(&reports, doc! { "resolution": 1, "date": 1, "id": 1 }, IndexOptions::builder().name("queue".to_string()).build()).await;
+
+    let collections = client.database("ouja_skins").collection::<CuratedCollection>("collections");
+    create_index(&collections, doc! { "owner": 1, "visibility": 1, "date": -1, "id": -1 }, IndexOptions::builder().name("owner".to_string()).build()).await;
+    create_index(&collections, doc! { "skins": 1 }, IndexOptions::builder().name("skins".to_string()).build()).await;
+
+    let follows = client.database("ouja_skins").collection::<FollowCollection>("follows");
+    create_index(&follows, doc! { "follower": 1, "followee": 1 }, IndexOptions::builder().name("follower_followee".to_string()).unique(true).build()).await;
+    create_index(&follows, doc! { "follower": 1, "date": -1, "followee": -1 }, IndexOptions::builder().name("following".to_string()).build()).await;
+    create_index(&follows, doc! { "followee": 1, "date": -1, "follower": -1 }, IndexOptions::builder().name("followers".to_string()).build()).await;
+
+    let blocks = client.database("ouja_skins").collection::<BlockCollection>("blocks");
+    create_index(&blocks, doc! { "blocker": 1, "blocked": 1 }, IndexOptions::builder().name("blocker_blocked".to_string()).unique(true).build()).await;
+    create_index(&blocks, doc! { "blocked": 1 }, IndexOptions::builder().name("blocked".to_string()).build()).await;
+
+    let notifications = client.database("ouja_skins").collection::<NotificationCollection>("notifications");
+    create_index(&notifications, doc! { "recipient": 1, "date": -1, "id": -1 }, IndexOptions::builder().name("recipient".to_string()).build()).await;
+    create_index(&notifications, doc! { "recipient": 1, "read": 1 }, IndexOptions::builder().name("unread".to_string()).build()).await;
+    create_index(&notifications, doc! { "expires": 1 }, IndexOptions::builder().name("expires".to_string()).expire_after(Duration::ZERO).build()).await;
+
+    let tokens = client.database("ouja_skins").collection::<YggdrasilToken>("yggdrasil_tokens");
+    create_index(&tokens, doc! { "access_token": 1 }, IndexOptions::builder().name("access_token".to_string()).unique(true).build()).await;
+    create_index(&tokens, doc! { "account": 1, "client_token": 1 }, IndexOptions::builder().name("account".to_string()).build()).await;
+    create_index(&tokens, doc! { "expires": 1 }, IndexOptions::builder().name("expires".to_string()).expire_after(Duration::ZERO).build()).await;
+
+    let joins = client.database("ouja_skins").collection::<YggdrasilJoin>("yggdrasil_joins");
+    create_index(&joins, doc! { "server_id": 1, "account": 1 }, IndexOptions::builder().name("server_id".to_string()).build()).await;
+    create_index(&joins, doc! { "expires": 1 }, IndexOptions::builder().name("expires".to_string()).expire_after(Duration::ZERO).build()).await;
+
+    let keys = client.database("ouja_skins").collection::<SigningKey>("signing_keys");
+    create_index(&keys, doc! { "activates": -1 }, IndexOptions::builder().name("activates".to_string()).build()).await;
+    create_index(&keys, doc! { "retires": 1 }, IndexOptions::builder().name("retires".to_string()).expire_after(Duration::ZERO).build()).await;
+}
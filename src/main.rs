@@ -3,9 +3,19 @@ use actix_web::{web, App, HttpServer};
 use env_logger::Env;
 use mongodb::Client;
 
+mod commands;
+mod cursor;
+mod database;
 mod magic_crypt;
+mod minecraft;
 mod models;
+mod notifications;
+mod render;
 mod routers;
+mod signing;
+mod stats;
+mod tags;
+mod texture;
 mod util;
 
 #[actix_web::main]
@@ -13,6 +23,10 @@ async fn main() -> std::io::Result<()> {
     dotenvy::dotenv().ok();
     env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
 
+    if let Err(err) = util::load_skin_resolutions().and_then(|_| util::load_cape_resolutions()) {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, err));
+    }
+
     println!("Starting API on {}", dotenvy::var("BIND_ADDR").unwrap());
 
     let uri = std::env::var("MONGODB").unwrap_or_else(|_| "mongodb://localhost:27017".into());
@@ -20,6 +34,24 @@ async fn main() -> std::io::Result<()> {
 
     println!("Connected to the database");
 
+    database::ensure_indexes(&client).await;
+
+    match std::env::args().nth(1).as_deref() {
+        Some("convert-legacy") => return commands::convert_legacy_skins(&client).await,
+        Some("backfill-phash") => return commands::backfill_phash(&client).await,
+        Some("backfill-hashes") => return commands::backfill_hashes(&client).await,
+        Some("recount-likes") => return commands::recount_likes(&client).await,
+        Some("backfill-offline-uuids") => return commands::backfill_offline_uuids(&client).await,
+        Some("rotate-signing-key") => return commands::rotate_signing_key(&client).await,
+        _ => {}
+    }
+
+    if let Err(err) = signing::ensure_signing_key(&client).await {
+        println!("{} - creating signing key", err);
+    }
+
+    stats::spawn_trending_task(client.clone());
+
     HttpServer::new(move || {
         App::new()
             .wrap(
@@ -0,0 +1,237 @@
+use std::time::Duration;
+
+use actix_web::web;
+use image::RgbaImage;
+use rand::Rng;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{
+    texture::decode_image,
+    util::{get_minecraft_api_url, get_minecraft_session_url},
+};
+
+// Pixels of the verification pattern, in the 8x8 corner left of the head top that no
+// skin layout renders, so painting them does not change how the skin looks in game.
+pub const PATTERN_PIXELS: [(u32, u32); 8] = [(0, 0), (2, 0), (4, 0), (6, 0), (1, 2), (3, 2), (5, 2), (7, 2)];
+
+// Mojang only serves 64x64 and 64x32 skins, anything much larger is not a skin.
+const MAX_SKIN_SIDE: u32 = 512;
+const MAX_SKIN_BYTES: usize = 1_000_000;
+
+#[derive(Deserialize)]
+struct ProfileProperty {
+    name: String,
+    value: String,
+}
+
+#[derive(Deserialize)]
+struct MojangProfile {
+    id: String,
+    name: String,
+    #[serde(default)]
+    properties: Vec<ProfileProperty>,
+}
+
+fn http_client() -> Result<reqwest::Client, String> {
+    reqwest::Client::builder().timeout(Duration::from_secs(10)).build().map_err(|err| err.to_string())
+}
+
+// Fetches a JSON document, `None` when Mojang answers that nothing exists there.
+async fn fetch_json<T: for<'de> Deserialize<'de>>(url: &str) -> Result<Option<T>, String> {
+    let response = http_client()?.get(url).send().await.map_err(|err| err.to_string())?;
+    match response.status() {
+        reqwest::StatusCode::NO_CONTENT | reqwest::StatusCode::NOT_FOUND => Ok(None),
+        status if status.is_success() => response.json().await.map(Some).map_err(|err| err.to_string()),
+        status => Err(format!("profile API answered {}", status)),
+    }
+}
+
+async fn fetch_profile(uuid: &Uuid) -> Result<Option<MojangProfile>, String> {
+    fetch_json(&format!("{}/session/minecraft/profile/{}", get_minecraft_session_url(), uuid.simple())).await
+}
+
+// Nothing else can be a Minecraft name, and it keeps the input out of the URL path.
+fn is_player_name(player: &str) -> bool {
+    !player.is_empty() && player.len() <= 16 && player.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// Looks up a Minecraft account by name or UUID, returns its dashed UUID and current name.
+pub async fn find_player(player: &str) -> Result<Option<(String, String)>, String> {
+    let profile = match Uuid::parse_str(player) {
+        Ok(uuid) => fetch_profile(&uuid).await?,
+        Err(_err) if !is_player_name(player) => None,
+        Err(_err) => fetch_json(&format!("{}/users/profiles/minecraft/{}", get_minecraft_api_url(), player)).await?,
+    };
+    match profile {
+        Some(profile) => match Uuid::parse_str(&profile.id) {
+            Ok(uuid) => Ok(Some((uuid.to_string(), profile.name))),
+            Err(err) => Err(err.to_string()),
+        },
+        None => Ok(None),
+    }
+}
+
+#[derive(Deserialize)]
+struct TexturesPayload {
+    textures: Textures,
+}
+
+#[derive(Deserialize)]
+struct Textures {
+    #[serde(rename = "SKIN")]
+    skin: Option<TextureEntry>,
+}
+
+#[derive(Deserialize)]
+struct TextureEntry {
+    url: String,
+}
+
+// The skin URL in the base64 `textures` property of a profile, `None` when no skin is worn.
+fn parse_skin_url(value: &str) -> Result<Option<String>, String> {
+    let payload = base64::decode(value).map_err(|err| err.to_string())?;
+    let payload: TexturesPayload = serde_json::from_slice(&payload).map_err(|err| err.to_string())?;
+    Ok(payload.textures.skin.map(|skin| skin.url))
+}
+
+// The skin the Minecraft account currently wears, with the name it has now.
+pub async fn fetch_skin(uuid: &str) -> Result<Option<(String, RgbaImage)>, String> {
+    let uuid = Uuid::parse_str(uuid).map_err(|err| err.to_string())?;
+    let Some(profile) = fetch_profile(&uuid).await? else {
+        return Ok(None);
+    };
+    let Some(property) = profile.properties.iter().find(|property| property.name == "textures") else {
+        return Ok(None);
+    };
+    let Some(url) = parse_skin_url(&property.value)? else {
+        return Ok(None);
+    };
+
+    let mut response = http_client()?.get(&url).send().await.map_err(|err| err.to_string())?;
+    if !response.status().is_success() {
+        return Err(format!("skin download answered {}", response.status()));
+    }
+    // The download is read in chunks so a huge answer is dropped before it is buffered.
+    let mut buffer = Vec::new();
+    while let Some(chunk) = response.chunk().await.map_err(|err| err.to_string())? {
+        if buffer.len() + chunk.len() > MAX_SKIN_BYTES {
+            return Err("skin download is too large".to_string());
+        }
+        buffer.extend_from_slice(&chunk);
+    }
+    match web::block(move || decode_image(&buffer, MAX_SKIN_SIDE)).await.map_err(|err| err.to_string())? {
+        Some((skin, _format)) => Ok(Some((profile.name, skin))),
+        None => Err("skin could not be decoded".to_string()),
+    }
+}
+
+// Random opaque colours, one for each pattern pixel.
+pub fn issue_pattern() -> Vec<String> {
+    let mut rng = rand::thread_rng();
+    PATTERN_PIXELS.iter().map(|_pixel| format!("{:06x}", rng.gen_range(0..0x1000000))).collect()
+}
+
+// Whether every pattern pixel has its colour, scaled along with HD skins.
+pub fn has_pattern(skin: &RgbaImage, pattern: &[String]) -> bool {
+    let scale = (skin.width() / 64).max(1);
+    pattern.len() == PATTERN_PIXELS.len()
+        && PATTERN_PIXELS.iter().zip(pattern).all(|(&(x, y), color)| {
+            let Ok(color) = u32::from_str_radix(color, 16) else {
+                return false;
+            };
+            let expected = [(color >> 16) as u8, (color >> 8) as u8, color as u8, 255];
+            skin.get_pixel_checked(x * scale, y * scale).map(|pixel| pixel.0) == Some(expected)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgba;
+
+    use super::*;
+
+    fn pattern() -> Vec<String> {
+        ["ff0000", "00ff00", "0000ff", "123456", "abcdef", "000000", "ffffff", "808080"].iter().map(|color| color.to_string()).collect()
+    }
+
+    fn painted(size: u32, pattern: &[String]) -> RgbaImage {
+        let mut skin = RgbaImage::new(size, size);
+        let scale = size / 64;
+        for (&(x, y), color) in PATTERN_PIXELS.iter().zip(pattern) {
+            let color = u32::from_str_radix(color, 16).unwrap();
+            let pixel = Rgba([(color >> 16) as u8, (color >> 8) as u8, color as u8, 255]);
+            for dx in 0..scale {
+                for dy in 0..scale {
+                    skin.put_pixel(x * scale + dx, y * scale + dy, pixel);
+                }
+            }
+        }
+        skin
+    }
+
+    #[test]
+    fn finds_pattern_on_a_64x64_skin() {
+        assert!(has_pattern(&painted(64, &pattern()), &pattern()));
+    }
+
+    #[test]
+    fn finds_pattern_scaled_on_an_hd_skin() {
+        assert!(has_pattern(&painted(128, &pattern()), &pattern()));
+        // Painted at 64x64 coordinates on an HD skin, the scaled pixels do not match.
+        let mut unscaled = RgbaImage::new(128, 128);
+        image::imageops::replace(&mut unscaled, &painted(64, &pattern()), 0, 0);
+        assert!(!has_pattern(&unscaled, &pattern()));
+    }
+
+    #[test]
+    fn rejects_a_wrong_colour() {
+        let mut wrong = pattern();
+        wrong[3] = "123457".to_string();
+        assert!(!has_pattern(&painted(64, &pattern()), &wrong));
+    }
+
+    #[test]
+    fn rejects_a_transparent_pixel() {
+        let mut skin = painted(64, &pattern());
+        skin.put_pixel(0, 0, Rgba([255, 0, 0, 0]));
+        assert!(!has_pattern(&skin, &pattern()));
+    }
+
+    #[test]
+    fn rejects_a_short_pattern() {
+        let skin = painted(64, &pattern());
+        assert!(!has_pattern(&skin, &pattern()[..7]));
+        assert!(!has_pattern(&skin, &[]));
+    }
+
+    #[test]
+    fn accepts_only_minecraft_names() {
+        assert!(is_player_name("Notch"));
+        assert!(is_player_name("a_b_1"));
+        assert!(is_player_name("abcdefghijklmnop"));
+        assert!(!is_player_name(""));
+        assert!(!is_player_name("abcdefghijklmnopq"));
+        assert!(!is_player_name("../users"));
+        assert!(!is_player_name("na me"));
+        assert!(!is_player_name("nämé"));
+    }
+
+    #[test]
+    fn reads_the_skin_url_from_a_textures_property() {
+        let payload = r#"{"timestamp":1,"profileId":"069a79f444e94726a5befca90e38aaf5","profileName":"Notch","textures":{"SKIN":{"url":"http://textures.minecraft.net/texture/abc"},"CAPE":{"url":"http://textures.minecraft.net/texture/def"}}}"#;
+        assert_eq!(parse_skin_url(&base64::encode(payload)), Ok(Some("http://textures.minecraft.net/texture/abc".to_string())));
+    }
+
+    #[test]
+    fn reads_a_profile_without_skin() {
+        let payload = r#"{"timestamp":1,"profileId":"069a79f444e94726a5befca90e38aaf5","profileName":"Notch","textures":{}}"#;
+        assert_eq!(parse_skin_url(&base64::encode(payload)), Ok(None));
+    }
+
+    #[test]
+    fn rejects_a_malformed_textures_property() {
+        assert!(parse_skin_url("not base64!").is_err());
+        assert!(parse_skin_url(&base64::encode("{}")).is_err());
+    }
+}
@@ -1,4 +1,4 @@
-use bson::DateTime;
+use bson::{bson, Bson, DateTime};
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -11,26 +11,445 @@ pub struct Accounts {
     pub session: Option<String>,
     pub about_me: Option<String>,
     pub profile_picture: Option<String>,
+    pub active_skin: Option<String>,
+    // The UUID offline mode servers give the player named like the account, see `offline_uuid`.
+    pub offline_uuid: Option<String>,
+    // The Minecraft account the account proved it owns.
+    pub minecraft: Option<MinecraftLink>,
+    pub active_cape: Option<String>,
+    #[serde(default)]
+    pub moderator: bool,
+    // Set by a moderator, a suspended account can still sign in and read but not change anything,
+    // and its uploads are left out of feeds.
+    #[serde(default)]
+    pub suspended: bool,
+    // Kinds of notifications the account does not want to receive.
+    #[serde(default)]
+    pub muted_notifications: Vec<NotificationKind>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct SkinCollection {
     pub date: DateTime,
     pub id: String,
+    #[serde(default)]
+    pub kind: TextureKind,
     pub hash: String,
     pub filename: String,
     pub title: String,
     pub description: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub category: Option<String>,
     pub size: usize,
     pub metadata: SkinMeta,
     pub owner: String,
+    pub phash: Option<String>,
+    #[serde(default)]
+    pub phash_bands: Vec<String>,
+    #[serde(default)]
+    pub suspected_copy_of: Vec<String>,
+    #[serde(default = "first_revision")]
+    pub revision: u32,
+    #[serde(default)]
+    pub revisions: Vec<SkinRevision>,
+    #[serde(default)]
+    pub likes: i64,
+    #[serde(default)]
+    pub views: i64,
+    #[serde(default)]
+    pub downloads: i64,
+    #[serde(default)]
+    pub trending: f64,
+    #[serde(default)]
+    pub comments_locked: bool,
 }
 
+pub fn first_revision() -> u32 {
+    1
+}
+
+// A user curated, ordered list of skins from any owner.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CuratedCollection {
+    pub id: String,
+    pub owner: String,
+    pub date: DateTime,
+    pub updated: DateTime,
+    pub title: String,
+    pub description: String,
+    #[serde(default)]
+    pub visibility: Visibility,
+    #[serde(default)]
+    pub skins: Vec<String>,
+    pub cover: Option<String>,
+}
+
+// Unlisted things can be opened by anyone with the link but are not listed anywhere.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Visibility {
+    #[default]
+    Public,
+    Unlisted,
+    Private,
+}
+
+impl Visibility {
+    pub fn parse(visibility: &str) -> Option<Visibility> {
+        match visibility.to_lowercase().as_str() {
+            "public" => Some(Visibility::Public),
+            "unlisted" => Some(Visibility::Unlisted),
+            "private" => Some(Visibility::Private),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Visibility::Public => "public",
+            Visibility::Unlisted => "unlisted",
+            Visibility::Private => "private",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct NotificationCollection {
+    pub id: String,
+    pub recipient: String,
+    pub kind: NotificationKind,
+    // The account that caused the notification, if any.
+    pub actor: Option<String>,
+    pub skin: Option<String>,
+    pub comment: Option<String>,
+    // Extra information, the resolution for moderation outcomes.
+    pub detail: Option<String>,
+    pub date: DateTime,
+    #[serde(default)]
+    pub read: bool,
+    // A TTL index removes the notification at this date.
+    pub expires: DateTime,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum NotificationKind {
+    Follow,
+    Like,
+    Comment,
+    Reply,
+    Moderation,
+}
+
+impl NotificationKind {
+    pub const ALL: [NotificationKind; 5] = [
+        NotificationKind::Follow,
+        NotificationKind::Like,
+        NotificationKind::Comment,
+        NotificationKind::Reply,
+        NotificationKind::Moderation,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            NotificationKind::Follow => "follow",
+            NotificationKind::Like => "like",
+            NotificationKind::Comment => "comment",
+            NotificationKind::Reply => "reply",
+            NotificationKind::Moderation => "moderation",
+        }
+    }
+}
+
+// A launcher login through the Yggdrasil endpoints. The client token identifies the
+// launcher installation, a TTL index removes the token at `expires`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct YggdrasilToken {
+    pub access_token: String,
+    pub client_token: String,
+    pub account: String,
+    pub issued: DateTime,
+    pub expires: DateTime,
+}
+
+// A Minecraft identity linked to an account. The UUID is stored with dashes like account ids.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MinecraftLink {
+    pub uuid: String,
+    pub name: String,
+    // What offline mode servers call the player, derived from `name`.
+    pub offline_uuid: String,
+    pub verified: DateTime,
+}
+
+// A pending claim of a Minecraft identity. It is verified once the skin of that Minecraft
+// account carries the pattern, a TTL index removes unverified claims at `expires`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MinecraftClaim {
+    pub account: String,
+    pub uuid: String,
+    pub name: String,
+    // Colours of the pattern pixels as hex, see `minecraft::PATTERN_PIXELS`.
+    pub pattern: Vec<String>,
+    pub created: DateTime,
+    pub expires: DateTime,
+}
+
+// An RSA key the textures property is signed with. The private key is stored encrypted.
+// A key signs from `activates` on until a newer key activates, and stays published until
+// `retires`, a TTL index removes it afterwards.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SigningKey {
+    pub id: String,
+    pub private_key: String,
+    pub public_key: String,
+    pub created: DateTime,
+    pub activates: DateTime,
+    pub retires: Option<DateTime>,
+}
+
+// A player joining a server, kept only for the few seconds the server needs to check it.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct YggdrasilJoin {
+    pub server_id: String,
+    pub account: String,
+    pub ip: Option<String>,
+    pub expires: DateTime,
+}
+
+// One account following another, the pair is unique.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FollowCollection {
+    pub follower: String,
+    pub followee: String,
+    pub date: DateTime,
+}
+
+// One account blocking another, the pair is unique. Blocking ends follows both ways.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BlockCollection {
+    pub blocker: String,
+    pub blocked: String,
+    pub date: DateTime,
+}
+
+// One account liking one skin, the pair is unique. `SkinCollection::likes` counts these.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LikeCollection {
+    pub account: String,
+    pub skin: String,
+    pub date: DateTime,
+}
+
+// A comment on a skin. Replies point to the comment they answer and to the top level
+// comment of their thread, so a whole thread can be listed at once.
 #[derive(Serialize, Deserialize, Debug)]
+pub struct CommentCollection {
+    pub id: String,
+    pub skin: String,
+    pub author: String,
+    pub text: String,
+    pub date: DateTime,
+    pub edited: Option<DateTime>,
+    pub parent: Option<String>,
+    pub root: Option<String>,
+    // Comments with replies are blanked instead of removed to keep the thread together.
+    #[serde(default)]
+    pub deleted: bool,
+}
+
+// Something a user reported, waiting for a moderator in the moderation queue.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ReportCollection {
+    pub id: String,
+    pub date: DateTime,
+    pub kind: ReportKind,
+    pub target: String,
+    pub skin: String,
+    pub reporter: String,
+    pub reason: String,
+    pub resolution: Option<ReportResolution>,
+    pub moderator: Option<String>,
+    pub resolved: Option<DateTime>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportKind {
+    Comment,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportResolution {
+    Dismissed,
+    Removed,
+}
+
+impl ReportResolution {
+    pub fn parse(action: &str) -> Option<ReportResolution> {
+        match action {
+            "dismiss" | "dismissed" => Some(ReportResolution::Dismissed),
+            "remove" | "removed" => Some(ReportResolution::Removed),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ReportResolution::Dismissed => "dismissed",
+            ReportResolution::Removed => "removed",
+        }
+    }
+}
+
+// Views or downloads of one skin during one hour.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StatBucket {
+    pub skin: String,
+    pub event: StatEvent,
+    pub bucket: DateTime,
+    pub count: i64,
+}
+
+// Marks a client as counted for a skin until `expires`, a TTL index removes it afterwards.
+// The client is a hash of its address and user agent.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StatHit {
+    pub skin: String,
+    pub event: StatEvent,
+    pub client: String,
+    pub expires: DateTime,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum StatEvent {
+    View,
+    Download,
+}
+
+impl StatEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            StatEvent::View => "view",
+            StatEvent::Download => "download",
+        }
+    }
+
+    // Field of `SkinCollection` holding the all time count.
+    pub fn counter_field(&self) -> &'static str {
+        match self {
+            StatEvent::View => "views",
+            StatEvent::Download => "downloads",
+        }
+    }
+}
+
+// A texture version of a skin. The top level fields of `SkinCollection` mirror the current one.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SkinRevision {
+    pub revision: u32,
+    pub date: DateTime,
+    pub hash: String,
+    pub filename: String,
+    pub size: usize,
+    pub metadata: SkinMeta,
+    pub phash: Option<String>,
+    // The near duplicate flag while this was the current revision, restored with it.
+    #[serde(default)]
+    pub suspected_copy_of: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum SkinMeta {
     Image {
         width: usize,
         height: usize,
         content_type: String,
+        #[serde(default)]
+        legacy: bool,
+        #[serde(default)]
+        model: SkinModel,
+    },
+    Cape {
+        width: usize,
+        height: usize,
+        content_type: String,
     },
 }
+
+// Skins and capes share the skins collection, documents from before capes existed have no kind and are skins.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TextureKind {
+    #[default]
+    Skin,
+    Cape,
+}
+
+impl TextureKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TextureKind::Skin => "skin",
+            TextureKind::Cape => "cape",
+        }
+    }
+
+    pub fn title(&self) -> &'static str {
+        match self {
+            TextureKind::Skin => "Skin",
+            TextureKind::Cape => "Cape",
+        }
+    }
+
+    // The array on the account document that holds the ids of this kind.
+    pub fn account_field(&self) -> &'static str {
+        match self {
+            TextureKind::Skin => "skins",
+            TextureKind::Cape => "capes",
+        }
+    }
+
+    // The account field holding the texture of this kind the account currently wears.
+    pub fn active_field(&self) -> &'static str {
+        match self {
+            TextureKind::Skin => "active_skin",
+            TextureKind::Cape => "active_cape",
+        }
+    }
+
+    pub fn filter(&self) -> Bson {
+        match self {
+            TextureKind::Skin => bson!({ "$ne": "cape" }),
+            TextureKind::Cape => bson!("cape"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SkinModel {
+    #[default]
+    Classic,
+    Slim,
+}
+
+impl SkinModel {
+    // The model name game clients expect in texture metadata.
+    pub fn mojang_name(&self) -> &'static str {
+        match self {
+            SkinModel::Classic => "default",
+            SkinModel::Slim => "slim",
+        }
+    }
+
+    pub fn parse(model: &str) -> Option<SkinModel> {
+        match &model.to_lowercase()[..] {
+            "classic" | "default" | "steve" => Some(SkinModel::Classic),
+            "slim" | "alex" => Some(SkinModel::Slim),
+            _ => None,
+        }
+    }
+}
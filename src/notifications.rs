@@ -0,0 +1,84 @@
+use bson::doc;
+use mongodb::{bson::DateTime, Client, Collection};
+use uuid::Uuid;
+
+use crate::{
+    models::{Accounts, NotificationCollection, NotificationKind},
+    util::get_notification_ttl_days,
+};
+
+// What a notification is about, everything except who receives it.
+pub struct Notice {
+    pub kind: NotificationKind,
+    pub actor: Option<String>,
+    pub skin: Option<String>,
+    pub comment: Option<String>,
+    pub detail: Option<String>,
+}
+
+impl Notice {
+    pub fn new(kind: NotificationKind) -> Notice {
+        Notice { kind, actor: None, skin: None, comment: None, detail: None }
+    }
+
+    pub fn actor(mut self, actor: &str) -> Notice {
+        self.actor = Some(actor.to_string());
+        self
+    }
+
+    pub fn skin(mut self, skin: &str) -> Notice {
+        self.skin = Some(skin.to_string());
+        self
+    }
+
+    pub fn comment(mut self, comment: &str) -> Notice {
+        self.comment = Some(comment.to_string());
+        self
+    }
+
+    pub fn detail(mut self, detail: &str) -> Notice {
+        self.detail = Some(detail.to_string());
+        self
+    }
+}
+
+// Stores a notification unless the recipient caused it or muted its kind.
+pub async fn notify(client: &Client, recipient: &str, notice: Notice) -> mongodb::error::Result<()> {
+    if notice.actor.as_deref() == Some(recipient) {
+        return Ok(());
+    }
+    let accounts: Collection<Accounts> = client.database("ouja_skins").collection("accounts");
+    match accounts.find_one(doc! { "id": recipient }, None).await? {
+        Some(account) if !account.muted_notifications.contains(&notice.kind) => {},
+        _ => return Ok(()),
+    }
+
+    let date = DateTime::now();
+    let notification = NotificationCollection {
+        id: Uuid::new_v4().to_string(),
+        recipient: recipient.to_string(),
+        kind: notice.kind,
+        actor: notice.actor,
+        skin: notice.skin,
+        comment: notice.comment,
+        detail: notice.detail,
+        date,
+        read: false,
+        expires: DateTime::from_millis(date.timestamp_millis() + get_notification_ttl_days() * 24 * 60 * 60 * 1000),
+    };
+    let notifications: Collection<NotificationCollection> = client.database("ouja_skins").collection("notifications");
+    notifications.insert_one(&notification, None).await?;
+    Ok(())
+}
+
+// Notifies in the background, a failure only gets logged.
+pub fn spawn_notify(client: &Client, recipient: &str, notice: Notice) {
+    let client = client.clone();
+    let recipient = recipient.to_string();
+    actix_web::rt::spawn(async move {
+        let kind = notice.kind;
+        if let Err(err) = notify(&client, &recipient, notice).await {
+            println!("{:?} - sending {} notification", err, kind.name());
+        }
+    });
+}
@@ -0,0 +1,419 @@
+use image::{Rgba, RgbaImage};
+
+use crate::{
+    models::SkinModel,
+    texture::{convert_legacy_skin, is_legacy},
+};
+
+// Internal supersampling factor, the canvas is rendered this many times larger
+// and averaged down to smooth the edges of the model.
+const SUPERSAMPLE: u32 = 2;
+
+pub struct RenderOptions {
+    pub yaw: f32,
+    pub pitch: f32,
+    pub light: f32,
+    pub ambient: f32,
+    pub scale: u32,
+    pub background: Option<Rgba<u8>>,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        RenderOptions {
+            yaw: 35.0,
+            pitch: 25.0,
+            light: 0.45,
+            ambient: 0.6,
+            scale: 8,
+            background: None,
+        }
+    }
+}
+
+// A cuboid of the player model, in model units (one unit is one texel of a 64px wide skin).
+struct Part {
+    min: [f32; 3],
+    size: [f32; 3],
+    uv: (f32, f32),
+    inflate: f32,
+    overlay: bool,
+}
+
+struct Face {
+    origin: [f32; 3],
+    s: [f32; 3],
+    t: [f32; 3],
+    normal: [f32; 3],
+    uv: (f32, f32),
+    texels: (f32, f32),
+}
+
+fn part(min: [f32; 3], size: [f32; 3], uv: (f32, f32)) -> Part {
+    Part {
+        min,
+        size,
+        uv,
+        inflate: 0.0,
+        overlay: false,
+    }
+}
+
+fn layer(min: [f32; 3], size: [f32; 3], uv: (f32, f32), inflate: f32) -> Part {
+    Part {
+        min,
+        size,
+        uv,
+        inflate,
+        overlay: true,
+    }
+}
+
+fn player_model(model: SkinModel) -> Vec<Part> {
+    let arm = match model {
+        SkinModel::Classic => 4.0,
+        SkinModel::Slim => 3.0,
+    };
+    vec![
+        part([-4.0, 24.0, -4.0], [8.0, 8.0, 8.0], (0.0, 0.0)),
+        part([-4.0, 12.0, -2.0], [8.0, 12.0, 4.0], (16.0, 16.0)),
+        part([-4.0 - arm, 12.0, -2.0], [arm, 12.0, 4.0], (40.0, 16.0)),
+        part([4.0, 12.0, -2.0], [arm, 12.0, 4.0], (32.0, 48.0)),
+        part([-4.0, 0.0, -2.0], [4.0, 12.0, 4.0], (0.0, 16.0)),
+        part([0.0, 0.0, -2.0], [4.0, 12.0, 4.0], (16.0, 48.0)),
+        layer([-4.0, 24.0, -4.0], [8.0, 8.0, 8.0], (32.0, 0.0), 0.5),
+        layer([-4.0, 12.0, -2.0], [8.0, 12.0, 4.0], (16.0, 32.0), 0.25),
+        layer([-4.0 - arm, 12.0, -2.0], [arm, 12.0, 4.0], (40.0, 32.0), 0.25),
+        layer([4.0, 12.0, -2.0], [arm, 12.0, 4.0], (48.0, 48.0), 0.25),
+        layer([-4.0, 0.0, -2.0], [4.0, 12.0, 4.0], (0.0, 32.0), 0.25),
+        layer([0.0, 0.0, -2.0], [4.0, 12.0, 4.0], (0.0, 48.0), 0.25),
+    ]
+}
+
+impl Part {
+    fn bounds(&self) -> ([f32; 3], [f32; 3]) {
+        let min = [
+            self.min[0] - self.inflate,
+            self.min[1] - self.inflate,
+            self.min[2] - self.inflate,
+        ];
+        let max = [
+            self.min[0] + self.size[0] + self.inflate,
+            self.min[1] + self.size[1] + self.inflate,
+            self.min[2] + self.size[2] + self.inflate,
+        ];
+        (min, max)
+    }
+
+    // Follows the vanilla box UV layout: top and bottom on the first row,
+    // then right, front, left and back wrapping around the box.
+    fn faces(&self) -> Vec<Face> {
+        let (min, max) = self.bounds();
+        let [w, h, d] = self.size;
+        let (u, v) = self.uv;
+        let sx = (max[0] - min[0]) / w;
+        let sy = (max[1] - min[1]) / h;
+        let sz = (max[2] - min[2]) / d;
+
+        vec![
+            Face {
+                origin: [min[0], max[1], max[2]],
+                s: [sx, 0.0, 0.0],
+                t: [0.0, -sy, 0.0],
+                normal: [0.0, 0.0, 1.0],
+                uv: (u + d, v + d),
+                texels: (w, h),
+            },
+            Face {
+                origin: [min[0], max[1], min[2]],
+                s: [0.0, 0.0, sz],
+                t: [0.0, -sy, 0.0],
+                normal: [-1.0, 0.0, 0.0],
+                uv: (u, v + d),
+                texels: (d, h),
+            },
+            Face {
+                origin: [max[0], max[1], max[2]],
+                s: [0.0, 0.0, -sz],
+                t: [0.0, -sy, 0.0],
+                normal: [1.0, 0.0, 0.0],
+                uv: (u + d + w, v + d),
+                texels: (d, h),
+            },
+            Face {
+                origin: [max[0], max[1], min[2]],
+                s: [-sx, 0.0, 0.0],
+                t: [0.0, -sy, 0.0],
+                normal: [0.0, 0.0, -1.0],
+                uv: (u + d + d + w, v + d),
+                texels: (w, h),
+            },
+            Face {
+                origin: [min[0], max[1], min[2]],
+                s: [sx, 0.0, 0.0],
+                t: [0.0, 0.0, sz],
+                normal: [0.0, 1.0, 0.0],
+                uv: (u + d, v),
+                texels: (w, d),
+            },
+            Face {
+                origin: [min[0], min[1], max[2]],
+                s: [sx, 0.0, 0.0],
+                t: [0.0, 0.0, -sz],
+                normal: [0.0, -1.0, 0.0],
+                uv: (u + d + w, v),
+                texels: (w, d),
+            },
+        ]
+    }
+}
+
+struct Camera {
+    yaw: (f32, f32),
+    pitch: (f32, f32),
+}
+
+impl Camera {
+    fn new(yaw: f32, pitch: f32) -> Self {
+        let yaw = yaw.to_radians();
+        let pitch = pitch.to_radians();
+        Camera {
+            yaw: (yaw.sin(), yaw.cos()),
+            pitch: (pitch.sin(), pitch.cos()),
+        }
+    }
+
+    // Rotates a point into view space, x to the right, y up and z towards the viewer.
+    fn rotate(&self, p: [f32; 3]) -> [f32; 3] {
+        let (ys, yc) = self.yaw;
+        let (ps, pc) = self.pitch;
+        let x = p[0] * yc + p[2] * ys;
+        let z = -p[0] * ys + p[2] * yc;
+        let y = p[1] * pc - z * ps;
+        let z = p[1] * ps + z * pc;
+        [x, y, z]
+    }
+}
+
+struct Canvas {
+    image: RgbaImage,
+    depth: Vec<f32>,
+    origin: (f32, f32),
+    scale: f32,
+}
+
+impl Canvas {
+    fn project(&self, p: [f32; 3]) -> [f32; 3] {
+        [
+            (p[0] - self.origin.0) * self.scale,
+            (self.origin.1 - p[1]) * self.scale,
+            p[2],
+        ]
+    }
+
+    fn fill_triangle(&mut self, a: [f32; 3], b: [f32; 3], c: [f32; 3], color: Rgba<u8>, blend: bool) {
+        let area = edge(a, b, c);
+        if area.abs() < f32::EPSILON {
+            return;
+        }
+
+        let (width, height) = self.image.dimensions();
+        let min_x = a[0].min(b[0]).min(c[0]).floor().max(0.0) as u32;
+        let min_y = a[1].min(b[1]).min(c[1]).floor().max(0.0) as u32;
+        let max_x = (a[0].max(b[0]).max(c[0]).ceil() as u32).min(width);
+        let max_y = (a[1].max(b[1]).max(c[1]).ceil() as u32).min(height);
+
+        for py in min_y..max_y {
+            for px in min_x..max_x {
+                let p = [px as f32 + 0.5, py as f32 + 0.5, 0.0];
+                let w0 = edge(b, c, p) / area;
+                let w1 = edge(c, a, p) / area;
+                let w2 = edge(a, b, p) / area;
+                if w0 < 0.0 || w1 < 0.0 || w2 < 0.0 {
+                    continue;
+                }
+
+                let z = w0 * a[2] + w1 * b[2] + w2 * c[2];
+                let index = (py * width + px) as usize;
+                if z <= self.depth[index] {
+                    continue;
+                }
+                self.depth[index] = z;
+
+                let pixel = self.image.get_pixel_mut(px, py);
+                if blend {
+                    *pixel = blend_over(*pixel, color);
+                } else {
+                    *pixel = color;
+                }
+            }
+        }
+    }
+}
+
+fn edge(a: [f32; 3], b: [f32; 3], p: [f32; 3]) -> f32 {
+    (b[0] - a[0]) * (p[1] - a[1]) - (b[1] - a[1]) * (p[0] - a[0])
+}
+
+fn blend_over(dst: Rgba<u8>, src: Rgba<u8>) -> Rgba<u8> {
+    let sa = src[3] as f32 / 255.0;
+    let da = dst[3] as f32 / 255.0;
+    let out = sa + da * (1.0 - sa);
+    if out <= 0.0 {
+        return Rgba([0, 0, 0, 0]);
+    }
+    let channel = |i: usize| {
+        ((src[i] as f32 * sa + dst[i] as f32 * da * (1.0 - sa)) / out).round() as u8
+    };
+    Rgba([channel(0), channel(1), channel(2), (out * 255.0).round() as u8])
+}
+
+fn add(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+fn mul(a: [f32; 3], k: f32) -> [f32; 3] {
+    [a[0] * k, a[1] * k, a[2] * k]
+}
+
+// Averages each block of supersampled pixels, weighting colours by alpha so
+// transparent backgrounds do not bleed dark fringes into the edges.
+fn downsample(image: &RgbaImage, factor: u32) -> RgbaImage {
+    let (width, height) = (image.width() / factor, image.height() / factor);
+    RgbaImage::from_fn(width, height, |x, y| {
+        let mut sum = [0.0f32; 4];
+        for dy in 0..factor {
+            for dx in 0..factor {
+                let pixel = image.get_pixel(x * factor + dx, y * factor + dy);
+                let alpha = pixel[3] as f32;
+                sum[0] += pixel[0] as f32 * alpha;
+                sum[1] += pixel[1] as f32 * alpha;
+                sum[2] += pixel[2] as f32 * alpha;
+                sum[3] += alpha;
+            }
+        }
+        if sum[3] == 0.0 {
+            return Rgba([0, 0, 0, 0]);
+        }
+        let samples = (factor * factor) as f32;
+        Rgba([
+            (sum[0] / sum[3]).round() as u8,
+            (sum[1] / sum[3]).round() as u8,
+            (sum[2] / sum[3]).round() as u8,
+            (sum[3] / samples).round() as u8,
+        ])
+    })
+}
+
+pub fn parse_hex_color(hex: &str) -> Option<Rgba<u8>> {
+    let hex = hex.trim_start_matches('#');
+    let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+    match hex.len() {
+        6 => Some(Rgba([channel(0)?, channel(2)?, channel(4)?, 255])),
+        8 => Some(Rgba([channel(0)?, channel(2)?, channel(4)?, channel(6)?])),
+        _ => None,
+    }
+}
+
+pub fn render_isometric(skin: &RgbaImage, model: SkinModel, options: &RenderOptions) -> RgbaImage {
+    let converted;
+    let skin = if is_legacy(skin.width(), skin.height()) {
+        converted = convert_legacy_skin(skin);
+        &converted
+    } else {
+        skin
+    };
+    let texel = skin.width() as f32 / 64.0;
+    let parts = player_model(model);
+    let camera = Camera::new(options.yaw, options.pitch);
+
+    // Fit the canvas around the projected corners of every part.
+    let (mut min_x, mut min_y) = (f32::MAX, f32::MAX);
+    let (mut max_x, mut max_y) = (f32::MIN, f32::MIN);
+    for part in &parts {
+        let (lo, hi) = part.bounds();
+        for corner in 0..8 {
+            let p = camera.rotate([
+                if corner & 1 == 0 { lo[0] } else { hi[0] },
+                if corner & 2 == 0 { lo[1] } else { hi[1] },
+                if corner & 4 == 0 { lo[2] } else { hi[2] },
+            ]);
+            min_x = min_x.min(p[0]);
+            max_x = max_x.max(p[0]);
+            min_y = min_y.min(p[1]);
+            max_y = max_y.max(p[1]);
+        }
+    }
+
+    let padding = 1.0;
+    let scale = (options.scale * SUPERSAMPLE) as f32;
+    let width = (((max_x - min_x + padding * 2.0) * options.scale as f32).ceil() as u32).max(1) * SUPERSAMPLE;
+    let height = (((max_y - min_y + padding * 2.0) * options.scale as f32).ceil() as u32).max(1) * SUPERSAMPLE;
+    let background = options.background.unwrap_or(Rgba([0, 0, 0, 0]));
+
+    let mut canvas = Canvas {
+        image: RgbaImage::from_pixel(width, height, background),
+        depth: vec![f32::MIN; (width * height) as usize],
+        origin: (min_x - padding, max_y + padding),
+        scale,
+    };
+
+    let light = {
+        let l = [-0.35f32, 0.75, 0.55];
+        let len = (l[0] * l[0] + l[1] * l[1] + l[2] * l[2]).sqrt();
+        [l[0] / len, l[1] / len, l[2] / len]
+    };
+
+    // The base layer goes first so the overlay can blend on top of it.
+    for overlay in [false, true] {
+        for part in parts.iter().filter(|part| part.overlay == overlay) {
+            for face in part.faces() {
+                let normal = camera.rotate(face.normal);
+                if normal[2] <= 0.0 {
+                    continue;
+                }
+                let diffuse = (normal[0] * light[0] + normal[1] * light[1] + normal[2] * light[2]).max(0.0);
+                let shade = (options.ambient + options.light * diffuse).clamp(0.0, 1.0);
+
+                let columns = (face.texels.0 * texel).round() as u32;
+                let rows = (face.texels.1 * texel).round() as u32;
+                for row in 0..rows {
+                    for column in 0..columns {
+                        let tx = (face.uv.0 * texel) as u32 + column;
+                        let ty = (face.uv.1 * texel) as u32 + row;
+                        if tx >= skin.width() || ty >= skin.height() {
+                            continue;
+                        }
+
+                        let mut color = *skin.get_pixel(tx, ty);
+                        if part.overlay {
+                            if color[3] == 0 {
+                                continue;
+                            }
+                        } else {
+                            color[3] = 255;
+                        }
+                        for channel in 0..3 {
+                            color[channel] = (color[channel] as f32 * shade).round() as u8;
+                        }
+
+                        let s0 = column as f32 / texel;
+                        let s1 = (column + 1) as f32 / texel;
+                        let t0 = row as f32 / texel;
+                        let t1 = (row + 1) as f32 / texel;
+                        let corner = |s: f32, t: f32| {
+                            let p = add(face.origin, add(mul(face.s, s), mul(face.t, t)));
+                            canvas.project(camera.rotate(p))
+                        };
+                        let (a, b, c, d) = (corner(s0, t0), corner(s1, t0), corner(s1, t1), corner(s0, t1));
+                        let blend = color[3] < 255;
+                        canvas.fill_triangle(a, b, c, color, blend);
+                        canvas.fill_triangle(a, c, d, color, blend);
+                    }
+                }
+            }
+        }
+    }
+
+    downsample(&canvas.image, SUPERSAMPLE)
+}
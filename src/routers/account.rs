@@ -1,13 +1,18 @@
+use std::fs;
+
+use actix_multipart::Multipart;
 use actix_web::{get, post, put, web, HttpRequest, HttpResponse, patch};
 use mongodb::{bson::{doc,  DateTime}, Client, Collection};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use uuid::Uuid;
 
+use super::skins::read_multipart;
 use crate::{
-    util::{get_session_token, verified_csrf},
+    util::{authenticate, get_avatar_max_size, get_session_token, get_skins_path, offline_uuid, verified_csrf},
     magic_crypt::{decrypt, encrypt},
-    models::Accounts,
+    models::{Accounts, SkinCollection, TextureKind},
+    texture::{avatar_variants, crop_square, decode_image, encode_png, skin_face, AVATAR_SIZES},
 };
 
 #[derive(Serialize, Deserialize)]
@@ -41,6 +46,16 @@ pub struct UpdateEmailParams {
     email: String
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct UpdateSkinParams {
+    skin: String
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct UpdateCapeParams {
+    cape: String
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct UpdateUserParams {
     username: String,
@@ -60,7 +75,10 @@ async fn me(client: web::Data<Client>, req: HttpRequest) -> HttpResponse {
                     "session": account.session,
                     "username": account.username,
                     "about_me": account.about_me,
-                    "profile_picture": account.profile_picture
+                    "profile_picture": account.profile_picture,
+                    "active_skin": account.active_skin,
+                    "active_cape": account.active_cape,
+                    "minecraft": account.minecraft
                 });
                 HttpResponse::Ok()
                     .json(json!({ "status": 200, "success": true, "account": respond }))
@@ -120,7 +138,14 @@ async fn register(client: web::Data<Client>, req: HttpRequest, params: web::Form
                                 password: encrypt(&params.password),
                                 session: None,
                                 about_me: None,
-                                profile_picture : None
+                                profile_picture : None,
+                                active_skin: None,
+                                offline_uuid: Some(offline_uuid(&params.username)),
+                                minecraft: None,
+                                active_cape: None,
+                                moderator: false,
+                                suspended: false,
+                                muted_notifications: Vec::new()
                             };
                             match collection.insert_one(&new_doc, None).await {
                                 Ok(_result) => HttpResponse::Ok().json(json!({ "status": 200, "success": true })),
@@ -148,7 +173,7 @@ async fn update_user(client: web::Data<Client>, req: HttpRequest, params: web::F
                 if params.username.len() > 16 {
                     return HttpResponse::Ok().json(json!({ "code": 200, "success": false, "error": "Username is too long!" }))
                 }
-                match collection.update_one(doc! { "id": account.id }, doc! { "$set": { "username": &params.username, "about_me": &params.about_me } }, None).await {
+                match collection.update_one(doc! { "id": account.id }, doc! { "$set": { "username": &params.username, "offline_uuid": offline_uuid(&params.username), "about_me": &params.about_me } }, None).await {
                     Ok(_update_result) => {
                         HttpResponse::Ok().json(json!({ "code": 200, "success": true, "account": doc! { "username": &params.username, "about_me": &params.about_me } }))
                     },
@@ -192,6 +217,149 @@ async fn update_email(client: web::Data<Client>, req: HttpRequest, params: web::
     }
 }
 
+// Makes one of the account's textures the one it wears, an empty id takes it off.
+async fn set_active_texture(client: &Client, req: &HttpRequest, texture: &str, kind: TextureKind) -> HttpResponse {
+    let account = match authenticate(client, req).await {
+        Ok(account) => account,
+        Err(response) => return response,
+    };
+    let collection: Collection<Accounts> = client.database("ouja_skins").collection("accounts");
+    let skins: Collection<SkinCollection> = client.database("ouja_skins").collection("skins");
+    let active = if texture.is_empty() {
+        None
+    } else {
+        match skins.find_one(doc! { "id": texture, "kind": kind.filter(), "owner": &account.id }, None).await {
+            Ok(Some(texture)) => Some(texture.id),
+            Ok(None) => return HttpResponse::NotFound().json(json!({ "status": 404, "success": false, "error": format!("{} not found", kind.title()) })),
+            Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+        }
+    };
+    match collection.update_one(doc! { "id": account.id }, doc! { "$set": { kind.active_field(): &active } }, None).await {
+        Ok(_update_result) => {
+            HttpResponse::Ok().json(json!({ "status": 200, "success": true, kind.active_field(): active }))
+        },
+        Err(err) => HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    }
+}
+
+// An empty skin id takes the active skin off.
+#[patch("/skin")]
+async fn update_active_skin(client: web::Data<Client>, req: HttpRequest, params: web::Form<UpdateSkinParams>) -> HttpResponse {
+    set_active_texture(&client, &req, &params.skin, TextureKind::Skin).await
+}
+
+// An empty cape id takes the active cape off.
+#[patch("/cape")]
+async fn update_cape(client: web::Data<Client>, req: HttpRequest, params: web::Form<UpdateCapeParams>) -> HttpResponse {
+    set_active_texture(&client, &req, &params.cape, TextureKind::Cape).await
+}
+
+// Largest picture, or stored skin, a profile picture is made from.
+const MAX_AVATAR_SOURCE_SIDE: u32 = 4096;
+
+fn avatar_path(id: &str, size: u32) -> String {
+    format!("{}/avatars/{}.{}.png", get_skins_path(), id, size)
+}
+
+// Sets the profile picture from an uploaded `picture` image, or from the face of one of
+// the account's skins when a `skin` id is sent instead. Every size in `AVATAR_SIZES` is stored.
+#[put("/profile-picture")]
+async fn update_profile_picture(client: web::Data<Client>, req: HttpRequest, mut payload: Multipart) -> HttpResponse {
+    let account = match authenticate(&client, &req).await {
+        Ok(account) => account,
+        Err(response) => return response,
+    };
+    let form = match read_multipart(&mut payload, "picture", get_avatar_max_size()).await {
+        Ok(form) => form,
+        Err(response) => return response,
+    };
+
+    let (square, pixel_art) = if let Some(skin_id) = form.fields.get("skin").filter(|skin| !skin.is_empty()) {
+        let skins: Collection<SkinCollection> = client.database("ouja_skins").collection("skins");
+        let skin = match skins.find_one(doc! { "id": skin_id, "kind": TextureKind::Skin.filter(), "owner": &account.id }, None).await {
+            Ok(Some(skin)) => skin,
+            Ok(None) => return HttpResponse::NotFound().json(json!({ "status": 404, "success": false, "error": "Skin not found" })),
+            Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+        };
+        let face = web::block(move || {
+            let buffer = fs::read(format!("{}/{}.png", get_skins_path(), skin.id))?;
+            Ok::<_, std::io::Error>(decode_image(&buffer, MAX_AVATAR_SOURCE_SIDE).map(|(texture, _format)| skin_face(&texture)))
+        });
+        match face.await {
+            Ok(Ok(Some(face))) => (face, true),
+            Ok(Ok(None)) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": "Stored skin could not be decoded" })),
+            Ok(Err(err)) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+            Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+        }
+    } else {
+        if form.buffer.is_empty() {
+            return HttpResponse::BadRequest().json(json!({ "status": 400, "success": false, "error": "Could not find picture file." }));
+        }
+        let buffer = form.buffer;
+        match web::block(move || decode_image(&buffer, MAX_AVATAR_SOURCE_SIDE).map(|(picture, _format)| crop_square(&picture))).await {
+            Ok(Some(square)) => (square, false),
+            Ok(None) => return HttpResponse::BadRequest().json(json!({ "status": 400, "success": false, "error": "Picture must be a valid png or jpeg!" })),
+            Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+        }
+    };
+
+    let id = Uuid::new_v4().to_string();
+    let stored = {
+        let id = id.clone();
+        web::block(move || -> Result<(), String> {
+            fs::create_dir_all(format!("{}/avatars", get_skins_path())).map_err(|err| err.to_string())?;
+            for (size, variant) in avatar_variants(&square, pixel_art) {
+                let buffer = encode_png(variant).map_err(|err| err.to_string())?;
+                fs::write(avatar_path(&id, size), buffer).map_err(|err| err.to_string())?;
+            }
+            Ok(())
+        })
+        .await
+    };
+    match stored {
+        Ok(Ok(())) => {},
+        Ok(Err(err)) => {
+            println!("{} - storing profile picture", err);
+            return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err }));
+        },
+        Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    }
+
+    let accounts: Collection<Accounts> = client.database("ouja_skins").collection("accounts");
+    if let Err(err) = accounts.update_one(doc! { "id": &account.id }, doc! { "$set": { "profile_picture": &id } }, None).await {
+        return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() }));
+    }
+    if let Some(previous) = account.profile_picture {
+        for size in AVATAR_SIZES {
+            if let Err(err) = fs::remove_file(avatar_path(&previous, size)) {
+                println!("{:?} - removing old profile picture", err);
+            }
+        }
+    }
+
+    let sizes: serde_json::Map<String, serde_json::Value> = AVATAR_SIZES
+        .iter()
+        .map(|size| (size.to_string(), json!(format!("/v1/avatars/{}/{}.png", id, size))))
+        .collect();
+    HttpResponse::Ok().json(json!({ "status": 200, "success": true, "profile_picture": id, "sizes": sizes }))
+}
+
+#[get("/{id}/{size}.png")]
+async fn get_avatar(path: web::Path<(String, u32)>) -> HttpResponse {
+    let (id, size) = path.into_inner();
+    if !AVATAR_SIZES.contains(&size) || Uuid::parse_str(&id).is_err() {
+        return HttpResponse::NotFound().json(json!({ "status": 404, "success": false, "error": "Profile picture not found" }));
+    }
+    match web::block(move || fs::read(avatar_path(&id, size))).await {
+        Ok(Ok(buffer)) => HttpResponse::Ok().content_type("image/png").body(buffer),
+        Ok(Err(err)) if err.kind() == std::io::ErrorKind::NotFound => {
+            HttpResponse::NotFound().json(json!({ "status": 404, "success": false, "error": "Profile picture not found" }))
+        },
+        Ok(Err(err)) => HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+        Err(err) => HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    }
+}
+
 #[post("/login")]
 async fn login(client: web::Data<Client>, req: HttpRequest, params: web::Form<LoginParams>) -> HttpResponse {
     if !verified_csrf(&req) {
@@ -0,0 +1,32 @@
+use actix_multipart::Multipart;
+use actix_web::{delete, get, patch, put, web, HttpRequest, HttpResponse};
+use mongodb::Client;
+
+use crate::models::TextureKind;
+
+use super::skins::{delete_texture, find_texture, serve_texture, update_texture, upload_texture, UpdateSkinParams};
+
+#[get("/{id}.json")]
+pub async fn get_cape(client: web::Data<Client>, id: web::Path<String>) -> HttpResponse {
+    find_texture(client, id.into_inner(), TextureKind::Cape).await
+}
+
+#[get("/{id}.png")]
+pub async fn get_cape_texture(client: web::Data<Client>, id: web::Path<String>) -> HttpResponse {
+    serve_texture(client, id.into_inner(), TextureKind::Cape).await
+}
+
+#[put("/upload")]
+pub async fn upload_cape(client: web::Data<Client>, payload: Multipart, req: HttpRequest) -> HttpResponse {
+    upload_texture(client, payload, req, TextureKind::Cape).await
+}
+
+#[patch("/{id}")]
+pub async fn update_cape(client: web::Data<Client>, id: web::Path<String>, req: HttpRequest, params: web::Form<UpdateSkinParams>) -> HttpResponse {
+    update_texture(client, id.into_inner(), req, params.into_inner(), TextureKind::Cape).await
+}
+
+#[delete("/{id}")]
+pub async fn delete_cape(client: web::Data<Client>, id: web::Path<String>, req: HttpRequest) -> HttpResponse {
+    delete_texture(client, id.into_inner(), req, TextureKind::Cape).await
+}
@@ -0,0 +1,457 @@
+use std::collections::{HashMap, HashSet};
+
+use actix_web::{delete, get, patch, post, put, web, HttpRequest, HttpResponse};
+use bson::{doc, Bson, Document};
+use futures_util::stream::StreamExt;
+use mongodb::{bson::DateTime, options::FindOptions, Client, Collection};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::{
+    cursor::Cursor,
+    models::{Accounts, CuratedCollection, TextureKind, Visibility},
+    util::{authenticate, escape_regex, session_account},
+};
+
+use super::skins::{check_text, visible_filter, RespondSkin};
+
+pub const MAX_COLLECTION_SKINS: usize = 500;
+
+fn collections(client: &Client) -> Collection<CuratedCollection> {
+    client.database("ouja_skins").collection("collections")
+}
+
+fn check_title(title: &str) -> Result<String, String> {
+    let title = title.trim();
+    if title.is_empty() {
+        return Err("Title cannot be empty!".to_string());
+    }
+    if title.len() > 32 {
+        return Err("Title cannot be larger than 32 characters!".to_string());
+    }
+    Ok(title.to_string())
+}
+
+fn parse_visibility(visibility: &str) -> Result<Visibility, String> {
+    Visibility::parse(visibility).ok_or_else(|| "Visibility must be public, unlisted or private!".to_string())
+}
+
+// Looks a collection up for reading. Private collections only exist for their owner.
+async fn find_readable(client: &Client, id: &str, viewer: Option<&Accounts>) -> Result<CuratedCollection, HttpResponse> {
+    match collections(client).find_one(doc! { "id": id }, None).await {
+        Ok(Some(collection)) if collection.visibility != Visibility::Private || viewer.is_some_and(|viewer| viewer.id == collection.owner) => Ok(collection),
+        Ok(_) => Err(HttpResponse::NotFound().json(json!({ "status": 404, "success": false, "error": "Collection not found" }))),
+        Err(err) => Err(HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() }))),
+    }
+}
+
+async fn find_owned(client: &Client, id: &str, req: &HttpRequest) -> Result<(Accounts, CuratedCollection), HttpResponse> {
+    let account = authenticate(client, req).await?;
+    match collections(client).find_one(doc! { "id": id }, None).await {
+        Ok(Some(collection)) if collection.owner == account.id => Ok((account, collection)),
+        Ok(Some(collection)) if collection.visibility != Visibility::Private => {
+            Err(HttpResponse::Forbidden().json(json!({ "status": 403, "success": false, "error": "You do not own this collection." })))
+        },
+        Ok(_) => Err(HttpResponse::NotFound().json(json!({ "status": 404, "success": false, "error": "Collection not found" }))),
+        Err(err) => Err(HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() }))),
+    }
+}
+
+// The skins of `ids` a reader may see, the same ones the feed and search list.
+fn readable_filter(ids: &[String]) -> Document {
+    let mut filter = visible_filter(TextureKind::Skin);
+    filter.insert("id", doc! { "$in": ids });
+    filter
+}
+
+// Resolves skin ids to the skins a reader may see, keeping the given order. Skins are
+// checked when the collection is read, so deleted or hidden skins simply drop out of it.
+pub async fn readable_skins(client: &Client, ids: &[String]) -> mongodb::error::Result<Vec<RespondSkin>> {
+    let skins: Collection<RespondSkin> = client.database("ouja_skins").collection("skins");
+    let mut found: HashMap<String, RespondSkin> = HashMap::new();
+    let mut cursor = skins.find(readable_filter(ids), None).await?;
+    while let Some(skin) = cursor.next().await {
+        let skin = skin?;
+        found.insert(skin.id.clone(), skin);
+    }
+    Ok(ids.iter().filter_map(|id| found.remove(id)).collect())
+}
+
+// Called when a texture is deleted.
+pub async fn remove_skin_from_collections(client: &Client, skin: &str) -> mongodb::error::Result<()> {
+    let collections = collections(client);
+    collections.update_many(doc! { "cover": skin }, doc! { "$set": { "cover": null } }, None).await?;
+    collections.update_many(doc! { "skins": skin }, doc! { "$pull": { "skins": skin } }, None).await?;
+    Ok(())
+}
+
+// The cover is the chosen skin if it can still be seen, otherwise the first skin that can.
+fn cover_candidates(collection: &CuratedCollection) -> impl Iterator<Item = &String> {
+    collection.cover.iter().chain(collection.skins.iter().take(10))
+}
+
+// Looks the covers of all collections up at once.
+async fn respond_collections(client: &Client, collections: &[CuratedCollection]) -> mongodb::error::Result<Vec<serde_json::Value>> {
+    let candidates: Vec<String> = collections.iter().flat_map(cover_candidates).cloned().collect();
+    let readable: HashSet<String> = readable_skins(client, &candidates).await?.into_iter().map(|skin| skin.id).collect();
+    Ok(collections
+        .iter()
+        .map(|collection| {
+            let cover = cover_candidates(collection).find(|id| readable.contains(*id));
+            json!({
+                "id": collection.id,
+                "owner": collection.owner,
+                "date": collection.date,
+                "updated": collection.updated,
+                "title": collection.title,
+                "description": collection.description,
+                "visibility": collection.visibility,
+                "cover": cover,
+                "skin_count": collection.skins.len()
+            })
+        })
+        .collect())
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CreateCollectionParams {
+    title: String,
+    description: Option<String>,
+    visibility: Option<String>,
+}
+
+#[post("")]
+pub async fn create_collection(client: web::Data<Client>, req: HttpRequest, params: web::Form<CreateCollectionParams>) -> HttpResponse {
+    let account = match authenticate(&client, &req).await {
+        Ok(account) => account,
+        Err(response) => return response,
+    };
+    let title = match check_title(&params.title) {
+        Ok(title) => title,
+        Err(error) => return HttpResponse::BadRequest().json(json!({ "status": 400, "success": false, "error": error })),
+    };
+    let description = params.description.as_deref().unwrap_or_default().trim().to_string();
+    if let Err(error) = check_text("Description", &description) {
+        return HttpResponse::BadRequest().json(json!({ "status": 400, "success": false, "error": error }));
+    }
+    let visibility = match params.visibility.as_deref().map(parse_visibility).transpose() {
+        Ok(visibility) => visibility.unwrap_or_default(),
+        Err(error) => return HttpResponse::BadRequest().json(json!({ "status": 400, "success": false, "error": error })),
+    };
+
+    let date = DateTime::now();
+    let collection = CuratedCollection {
+        id: Uuid::new_v4().to_string(),
+        owner: account.id,
+        date,
+        updated: date,
+        title,
+        description,
+        visibility,
+        skins: Vec::new(),
+        cover: None,
+    };
+    match collections(&client).insert_one(&collection, None).await {
+        Ok(_result) => HttpResponse::Ok().json(json!({ "status": 200, "success": true, "collection": collection.id })),
+        Err(err) => HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CollectionSkinsParams {
+    limit: Option<i64>,
+    cursor: Option<String>,
+}
+
+#[get("/{id}")]
+pub async fn get_collection(client: web::Data<Client>, id: web::Path<String>, req: HttpRequest, params: web::Query<CollectionSkinsParams>) -> HttpResponse {
+    let viewer = session_account(&client, &req).await.ok();
+    let collection = match find_readable(&client, &id.into_inner(), viewer.as_ref()).await {
+        Ok(collection) => collection,
+        Err(response) => return response,
+    };
+    let limit = params.limit.unwrap_or(24).clamp(1, 100) as usize;
+    let offset = match params.cursor.as_deref().map(Cursor::decode) {
+        None => 0,
+        Some(Some(Cursor::Offset { sort, offset })) if sort == "position" => offset as usize,
+        Some(_) => return HttpResponse::BadRequest().json(json!({ "status": 400, "success": false, "error": "Invalid cursor!" })),
+    };
+
+    // Skins that can no longer be seen are skipped, so pages are filled from
+    // further down the list until they are full or the list ends.
+    let mut skins: Vec<RespondSkin> = Vec::new();
+    let mut position = offset;
+    while skins.len() < limit && position < collection.skins.len() {
+        let end = (position + limit - skins.len()).min(collection.skins.len());
+        match readable_skins(&client, &collection.skins[position..end]).await {
+            Ok(found) => skins.extend(found),
+            Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+        }
+        position = end;
+    }
+    let next_cursor = (position < collection.skins.len()).then(|| Cursor::Offset { sort: "position".to_string(), offset: position as u64 }.encode());
+
+    match respond_collections(&client, std::slice::from_ref(&collection)).await {
+        Ok(response) => HttpResponse::Ok().json(json!({ "status": 200, "success": true, "collection": response.first(), "skins": skins, "next_cursor": next_cursor })),
+        Err(err) => HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct UpdateCollectionParams {
+    title: Option<String>,
+    description: Option<String>,
+    visibility: Option<String>,
+    // An empty cover goes back to the first skin.
+    cover: Option<String>,
+    // Every skin of the collection, comma separated, in the new order.
+    order: Option<String>,
+}
+
+#[patch("/{id}")]
+pub async fn update_collection(client: web::Data<Client>, id: web::Path<String>, req: HttpRequest, params: web::Form<UpdateCollectionParams>) -> HttpResponse {
+    let (_account, collection) = match find_owned(&client, &id.into_inner(), &req).await {
+        Ok(found) => found,
+        Err(response) => return response,
+    };
+    let params = params.into_inner();
+    let mut update = doc! { "updated": DateTime::now() };
+
+    if let Some(title) = params.title {
+        match check_title(&title) {
+            Ok(title) => update.insert("title", title),
+            Err(error) => return HttpResponse::BadRequest().json(json!({ "status": 400, "success": false, "error": error })),
+        };
+    }
+    if let Some(description) = params.description {
+        let description = description.trim();
+        if let Err(error) = check_text("Description", description) {
+            return HttpResponse::BadRequest().json(json!({ "status": 400, "success": false, "error": error }));
+        }
+        update.insert("description", description);
+    }
+    if let Some(visibility) = params.visibility {
+        match parse_visibility(&visibility) {
+            Ok(visibility) => update.insert("visibility", visibility.name()),
+            Err(error) => return HttpResponse::BadRequest().json(json!({ "status": 400, "success": false, "error": error })),
+        };
+    }
+    if let Some(cover) = params.cover {
+        if cover.is_empty() {
+            update.insert("cover", Bson::Null);
+        } else if collection.skins.contains(&cover) {
+            update.insert("cover", cover);
+        } else {
+            return HttpResponse::BadRequest().json(json!({ "status": 400, "success": false, "error": "The cover must be a skin of the collection." }));
+        }
+    }
+
+    let mut filter = doc! { "id": &collection.id };
+    if let Some(order) = params.order {
+        let order: Vec<String> = order.split(',').map(str::trim).filter(|id| !id.is_empty()).map(str::to_string).collect();
+        let mut sorted = order.clone();
+        sorted.sort();
+        sorted.dedup();
+        let mut current = collection.skins.clone();
+        current.sort();
+        if sorted.len() != order.len() || sorted != current {
+            return HttpResponse::BadRequest().json(json!({ "status": 400, "success": false, "error": "The order must list every skin of the collection once." }));
+        }
+        // Only applies if nobody added or removed a skin since the collection was read.
+        filter.insert("skins", &collection.skins);
+        update.insert("skins", order);
+    }
+
+    match collections(&client).update_one(filter, doc! { "$set": update }, None).await {
+        Ok(result) if result.matched_count == 0 => {
+            HttpResponse::Conflict().json(json!({ "status": 409, "success": false, "error": "The collection changed, try again." }))
+        },
+        Ok(_result) => HttpResponse::Ok().json(json!({ "status": 200, "success": true })),
+        Err(err) => HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    }
+}
+
+#[delete("/{id}")]
+pub async fn delete_collection(client: web::Data<Client>, id: web::Path<String>, req: HttpRequest) -> HttpResponse {
+    let (_account, collection) = match find_owned(&client, &id.into_inner(), &req).await {
+        Ok(found) => found,
+        Err(response) => return response,
+    };
+    match collections(&client).delete_one(doc! { "id": &collection.id }, None).await {
+        Ok(_result) => HttpResponse::Ok().json(json!({ "status": 200, "success": true })),
+        Err(err) => HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AddSkinParams {
+    // Where to insert the skin, at the end when left out.
+    position: Option<usize>,
+}
+
+#[put("/{id}/skins/{skin}")]
+pub async fn add_collection_skin(client: web::Data<Client>, path: web::Path<(String, String)>, req: HttpRequest, params: web::Form<AddSkinParams>) -> HttpResponse {
+    let (id, skin) = path.into_inner();
+    let (_account, collection) = match find_owned(&client, &id, &req).await {
+        Ok(found) => found,
+        Err(response) => return response,
+    };
+    match readable_skins(&client, std::slice::from_ref(&skin)).await {
+        Ok(found) if found.is_empty() => return HttpResponse::NotFound().json(json!({ "status": 404, "success": false, "error": "Skin not found" })),
+        Ok(_found) => {},
+        Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    }
+
+    // The filter makes the push a no-op when the skin is already there or the collection is full.
+    let position = params.position.unwrap_or(collection.skins.len()).min(collection.skins.len()) as i64;
+    let result = collections(&client)
+        .update_one(
+            doc! { "id": &collection.id, "skins": { "$ne": &skin }, format!("skins.{}", MAX_COLLECTION_SKINS - 1): { "$exists": false } },
+            doc! { "$push": { "skins": { "$each": [&skin], "$position": position } }, "$set": { "updated": DateTime::now() } },
+            None,
+        )
+        .await;
+    match result {
+        Ok(result) if result.modified_count > 0 => HttpResponse::Ok().json(json!({ "status": 200, "success": true })),
+        Ok(_result) if collection.skins.contains(&skin) => HttpResponse::Ok().json(json!({ "status": 200, "success": true })),
+        Ok(_result) => HttpResponse::BadRequest().json(json!({ "status": 400, "success": false, "error": format!("A collection cannot hold more than {} skins.", MAX_COLLECTION_SKINS) })),
+        Err(err) => HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    }
+}
+
+#[delete("/{id}/skins/{skin}")]
+pub async fn remove_collection_skin(client: web::Data<Client>, path: web::Path<(String, String)>, req: HttpRequest) -> HttpResponse {
+    let (id, skin) = path.into_inner();
+    let (_account, collection) = match find_owned(&client, &id, &req).await {
+        Ok(found) => found,
+        Err(response) => return response,
+    };
+    let mut set = doc! { "updated": DateTime::now() };
+    if collection.cover.as_ref() == Some(&skin) {
+        set.insert("cover", Bson::Null);
+    }
+    match collections(&client).update_one(doc! { "id": &collection.id }, doc! { "$pull": { "skins": &skin }, "$set": set }, None).await {
+        Ok(_result) => HttpResponse::Ok().json(json!({ "status": 200, "success": true })),
+        Err(err) => HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct UserCollectionsParams {
+    limit: Option<i64>,
+    cursor: Option<String>,
+}
+
+// Other users only see public collections, the owner sees all of theirs.
+#[get("/{username}/collections")]
+pub async fn get_user_collections(client: web::Data<Client>, username: web::Path<String>, req: HttpRequest, params: web::Query<UserCollectionsParams>) -> HttpResponse {
+    let username = username.into_inner();
+    let limit = params.limit.unwrap_or(24).clamp(1, 100);
+    let accounts: Collection<Accounts> = client.database("ouja_skins").collection("accounts");
+    let account = match accounts.find_one(doc! { "username": { "$regex": format!("^{}$", escape_regex(&username)), "$options": "i" } }, None).await {
+        Ok(Some(account)) => account,
+        Ok(None) => return HttpResponse::NotFound().json(json!({ "status": 404, "success": false, "error": "User not found" })),
+        Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    };
+    let viewer = session_account(&client, &req).await.ok();
+
+    let mut filter = doc! { "owner": &account.id };
+    if viewer.map(|viewer| viewer.id) != Some(account.id) {
+        filter.insert("visibility", Visibility::Public.name());
+    }
+    let total = match collections(&client).count_documents(filter.clone(), None).await {
+        Ok(total) => total,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    };
+
+    let mut page_filter = filter;
+    match params.cursor.as_deref().map(Cursor::decode) {
+        None => {},
+        Some(Some(Cursor::After { sort, date, id })) if sort == "newest" => {
+            let date = DateTime::from_millis(date);
+            page_filter.insert("$or", vec![
+                doc! { "date": { "$lt": date } },
+                doc! { "date": date, "id": { "$lt": id } },
+            ]);
+        },
+        Some(_) => return HttpResponse::BadRequest().json(json!({ "status": 400, "success": false, "error": "Invalid cursor!" })),
+    }
+
+    let options = FindOptions::builder().sort(doc! { "date": -1, "id": -1 }).limit(limit + 1).build();
+    let mut cursor = match collections(&client).find(page_filter, options).await {
+        Ok(cursor) => cursor,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    };
+    let mut page: Vec<CuratedCollection> = Vec::new();
+    while let Some(collection) = cursor.next().await {
+        match collection {
+            Ok(collection) => page.push(collection),
+            Err(err) => {
+                println!("{:?} - collecting collections", err);
+                return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() }));
+            }
+        }
+    }
+
+    // One extra collection is fetched to know whether there is a next page.
+    let mut next_cursor = None;
+    if page.len() as i64 > limit {
+        page.truncate(limit as usize);
+        if let Some(last) = page.last() {
+            next_cursor = Some(Cursor::after("newest", last.date, &last.id).encode());
+        }
+    }
+
+    match respond_collections(&client, &page).await {
+        Ok(results) => HttpResponse::Ok().json(json!({ "status": 200, "success": true, "collections": results, "total": total, "next_cursor": next_cursor })),
+        Err(err) => HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Evaluates the operators `readable_skins` filters with against a stored document.
+    fn matches(filter: &Document, document: &Document) -> bool {
+        filter.iter().all(|(path, condition)| {
+            let mut value = Some(Bson::Document(document.clone()));
+            for key in path.split('.') {
+                value = match value {
+                    Some(Bson::Document(inner)) => inner.get(key).cloned(),
+                    Some(Bson::Array(items)) => key.parse::<usize>().ok().and_then(|index| items.get(index).cloned()),
+                    _ => None,
+                };
+            }
+            match condition {
+                Bson::Document(operators) => operators.iter().all(|(operator, operand)| match operator.as_str() {
+                    "$ne" => value.as_ref() != Some(operand),
+                    "$exists" => value.is_some() == operand.as_bool().unwrap(),
+                    "$in" => operand.as_array().unwrap().iter().any(|item| value.as_ref() == Some(item)),
+                    operator => panic!("unexpected operator {}", operator),
+                }),
+                condition => value.as_ref() == Some(condition),
+            }
+        })
+    }
+
+    fn ids(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    #[test]
+    fn unflagged_skin_stays_visible_in_a_collection() {
+        let skin = doc! { "id": "a", "kind": "skin", "suspected_copy_of": [] };
+        assert!(matches(&readable_filter(&ids(&["a", "b"])), &skin));
+        // Skins stored before capes and copy detection existed have neither field.
+        assert!(matches(&readable_filter(&ids(&["a"])), &doc! { "id": "a" }));
+    }
+
+    #[test]
+    fn flagged_skins_and_capes_are_left_out() {
+        assert!(!matches(&readable_filter(&ids(&["a"])), &doc! { "id": "a", "kind": "skin", "suspected_copy_of": ["b"] }));
+        assert!(!matches(&readable_filter(&ids(&["a"])), &doc! { "id": "a", "kind": "cape", "suspected_copy_of": [] }));
+        assert!(!matches(&readable_filter(&ids(&["b"])), &doc! { "id": "a", "kind": "skin", "suspected_copy_of": [] }));
+    }
+}
@@ -0,0 +1,411 @@
+use std::collections::HashMap;
+
+use actix_web::{delete, get, patch, post, put, web, HttpRequest, HttpResponse};
+use bson::doc;
+use futures_util::stream::StreamExt;
+use mongodb::{
+    bson::DateTime,
+    options::FindOptions,
+    Client, Collection,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::{
+    cursor::Cursor,
+    models::{CommentCollection, NotificationKind, ReportCollection, ReportKind, SkinCollection, TextureKind},
+    notifications::{spawn_notify, Notice},
+    util::{authenticate, find_usernames, is_duplicate_key},
+};
+
+use super::skins::check_text;
+
+async fn find_skin(client: &Client, id: &str) -> Result<SkinCollection, HttpResponse> {
+    let skins: Collection<SkinCollection> = client.database("ouja_skins").collection("skins");
+    match skins.find_one(doc! { "id": id, "kind": TextureKind::Skin.filter() }, None).await {
+        Ok(Some(skin)) => Ok(skin),
+        Ok(None) => Err(HttpResponse::NotFound().json(json!({ "status": 404, "success": false, "error": "Skin not found" }))),
+        Err(err) => Err(HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() }))),
+    }
+}
+
+async fn find_comment(client: &Client, skin: &str, id: &str) -> Result<CommentCollection, HttpResponse> {
+    let comments: Collection<CommentCollection> = client.database("ouja_skins").collection("comments");
+    match comments.find_one(doc! { "id": id, "skin": skin }, None).await {
+        Ok(Some(comment)) => Ok(comment),
+        Ok(None) => Err(HttpResponse::NotFound().json(json!({ "status": 404, "success": false, "error": "Comment not found" }))),
+        Err(err) => Err(HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() }))),
+    }
+}
+
+fn check_comment(text: &str) -> Result<String, String> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Err("Comment cannot be empty!".to_string());
+    }
+    check_text("Comment", text)?;
+    Ok(text.to_string())
+}
+
+// Removes a comment, or blanks it when others replied to it. Open reports about it
+// are closed since there is nothing left to moderate.
+pub async fn remove_comment(client: &Client, comment: &CommentCollection) -> mongodb::error::Result<()> {
+    let comments: Collection<CommentCollection> = client.database("ouja_skins").collection("comments");
+    let reports: Collection<ReportCollection> = client.database("ouja_skins").collection("reports");
+    if comments.count_documents(doc! { "parent": &comment.id }, None).await? > 0 {
+        comments
+            .update_one(doc! { "id": &comment.id }, doc! { "$set": { "deleted": true, "text": "" } }, None)
+            .await?;
+    } else {
+        comments.delete_one(doc! { "id": &comment.id }, None).await?;
+    }
+    reports
+        .update_many(
+            doc! { "target": &comment.id, "resolution": null },
+            doc! { "$set": { "resolution": "removed", "resolved": DateTime::now() } },
+            None,
+        )
+        .await?;
+    Ok(())
+}
+
+// Called when a texture is deleted.
+pub async fn remove_skin_comments(client: &Client, skin: &str) -> mongodb::error::Result<()> {
+    let comments: Collection<CommentCollection> = client.database("ouja_skins").collection("comments");
+    let reports: Collection<ReportCollection> = client.database("ouja_skins").collection("reports");
+    comments.delete_many(doc! { "skin": skin }, None).await?;
+    reports.delete_many(doc! { "skin": skin }, None).await?;
+    Ok(())
+}
+
+pub fn respond_comment(comment: &CommentCollection, usernames: &HashMap<String, String>) -> serde_json::Value {
+    json!({
+        "id": comment.id,
+        "skin": comment.skin,
+        "author": if comment.deleted { None } else { Some(&comment.author) },
+        "author_name": if comment.deleted { None } else { usernames.get(&comment.author) },
+        "text": comment.text,
+        "date": comment.date,
+        "edited": comment.edited,
+        "parent": comment.parent,
+        "deleted": comment.deleted
+    })
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CommentsParams {
+    limit: Option<i64>,
+    cursor: Option<String>,
+    thread: Option<String>,
+}
+
+// Lists the top level comments of a skin oldest first with their number of replies,
+// or with `thread` set, the replies to one top level comment.
+#[get("/{id}/comments")]
+pub async fn get_comments(client: web::Data<Client>, id: web::Path<String>, params: web::Query<CommentsParams>) -> HttpResponse {
+    let id = id.into_inner();
+    let limit = params.limit.unwrap_or(24).clamp(1, 100);
+    let skin = match find_skin(&client, &id).await {
+        Ok(skin) => skin,
+        Err(response) => return response,
+    };
+    let comments: Collection<CommentCollection> = client.database("ouja_skins").collection("comments");
+
+    let filter = doc! { "skin": &id, "root": params.thread.as_deref() };
+    let total = match comments.count_documents(filter.clone(), None).await {
+        Ok(total) => total,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    };
+
+    let mut page_filter = filter;
+    match params.cursor.as_deref().map(Cursor::decode) {
+        None => {},
+        Some(Some(Cursor::After { sort, date, id })) if sort == "oldest" => {
+            let date = DateTime::from_millis(date);
+            page_filter.insert("$or", vec![
+                doc! { "date": { "$gt": date } },
+                doc! { "date": date, "id": { "$gt": id } },
+            ]);
+        },
+        Some(_) => return HttpResponse::BadRequest().json(json!({ "status": 400, "success": false, "error": "Invalid cursor!" })),
+    }
+
+    let options = FindOptions::builder().sort(doc! { "date": 1, "id": 1 }).limit(limit + 1).build();
+    let mut cursor = match comments.find(page_filter, options).await {
+        Ok(cursor) => cursor,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    };
+    let mut page: Vec<CommentCollection> = Vec::new();
+    while let Some(comment) = cursor.next().await {
+        match comment {
+            Ok(comment) => page.push(comment),
+            Err(err) => {
+                println!("{:?} - collecting comments", err);
+                return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() }));
+            }
+        }
+    }
+
+    // One extra comment is fetched to know whether there is a next page.
+    let mut next_cursor = None;
+    if page.len() as i64 > limit {
+        page.truncate(limit as usize);
+        if let Some(last) = page.last() {
+            next_cursor = Some(Cursor::after("oldest", last.date, &last.id).encode());
+        }
+    }
+
+    let mut replies: HashMap<String, i32> = HashMap::new();
+    if params.thread.is_none() {
+        let ids: Vec<&String> = page.iter().map(|comment| &comment.id).collect();
+        let pipeline = vec![
+            doc! { "$match": { "root": { "$in": ids } } },
+            doc! { "$group": { "_id": "$root", "count": { "$sum": 1 } } },
+        ];
+        match comments.aggregate(pipeline, None).await {
+            Ok(mut cursor) => {
+                while let Some(Ok(count)) = cursor.next().await {
+                    if let (Ok(root), Ok(count)) = (count.get_str("_id"), count.get_i32("count")) {
+                        replies.insert(root.to_string(), count);
+                    }
+                }
+            },
+            Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+        }
+    }
+
+    let authors: Vec<&String> = page.iter().map(|comment| &comment.author).collect();
+    let usernames = match find_usernames(&client, &authors).await {
+        Ok(usernames) => usernames,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    };
+    let results: Vec<serde_json::Value> = page
+        .iter()
+        .map(|comment| {
+            let mut response = respond_comment(comment, &usernames);
+            if params.thread.is_none() {
+                response["replies"] = json!(replies.get(&comment.id).copied().unwrap_or(0));
+            }
+            response
+        })
+        .collect();
+
+    HttpResponse::Ok().json(json!({
+        "status": 200,
+        "success": true,
+        "comments": results,
+        "total": total,
+        "locked": skin.comments_locked,
+        "next_cursor": next_cursor
+    }))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CreateCommentParams {
+    text: String,
+    parent: Option<String>,
+}
+
+#[post("/{id}/comments")]
+pub async fn create_comment(client: web::Data<Client>, id: web::Path<String>, req: HttpRequest, params: web::Form<CreateCommentParams>) -> HttpResponse {
+    let id = id.into_inner();
+    let account = match authenticate(&client, &req).await {
+        Ok(account) => account,
+        Err(response) => return response,
+    };
+    let skin = match find_skin(&client, &id).await {
+        Ok(skin) => skin,
+        Err(response) => return response,
+    };
+    if skin.comments_locked {
+        return HttpResponse::Forbidden().json(json!({ "status": 403, "success": false, "error": "Comments are locked on this skin." }));
+    }
+    let text = match check_comment(&params.text) {
+        Ok(text) => text,
+        Err(error) => return HttpResponse::BadRequest().json(json!({ "status": 400, "success": false, "error": error })),
+    };
+
+    let (parent, root, parent_author) = match params.parent.as_deref().filter(|parent| !parent.is_empty()) {
+        Some(parent) => match find_comment(&client, &id, parent).await {
+            Ok(parent) if parent.deleted => {
+                return HttpResponse::BadRequest().json(json!({ "status": 400, "success": false, "error": "Cannot reply to a deleted comment." }));
+            },
+            Ok(parent) => (Some(parent.id.clone()), Some(parent.root.unwrap_or(parent.id)), Some(parent.author)),
+            Err(response) => return response,
+        },
+        None => (None, None, None),
+    };
+
+    let comment = CommentCollection {
+        id: Uuid::new_v4().to_string(),
+        skin: id,
+        author: account.id.clone(),
+        text,
+        date: DateTime::now(),
+        edited: None,
+        parent,
+        root,
+        deleted: false,
+    };
+    let comments: Collection<CommentCollection> = client.database("ouja_skins").collection("comments");
+    match comments.insert_one(&comment, None).await {
+        Ok(_result) => {
+            let notice = |kind| Notice::new(kind).actor(&account.id).skin(&comment.skin).comment(&comment.id);
+            spawn_notify(&client, &skin.owner, notice(NotificationKind::Comment));
+            if let Some(parent_author) = parent_author.filter(|author| *author != skin.owner) {
+                spawn_notify(&client, &parent_author, notice(NotificationKind::Reply));
+            }
+            let usernames = HashMap::from([(account.id, account.username)]);
+            HttpResponse::Ok().json(json!({ "status": 200, "success": true, "comment": respond_comment(&comment, &usernames) }))
+        },
+        Err(err) => HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct UpdateCommentParams {
+    text: String,
+}
+
+#[patch("/{id}/comments/{comment}")]
+pub async fn update_comment(client: web::Data<Client>, path: web::Path<(String, String)>, req: HttpRequest, params: web::Form<UpdateCommentParams>) -> HttpResponse {
+    let (id, comment_id) = path.into_inner();
+    let account = match authenticate(&client, &req).await {
+        Ok(account) => account,
+        Err(response) => return response,
+    };
+    let skin = match find_skin(&client, &id).await {
+        Ok(skin) => skin,
+        Err(response) => return response,
+    };
+    let comment = match find_comment(&client, &id, &comment_id).await {
+        Ok(comment) if !comment.deleted => comment,
+        Ok(_deleted) => return HttpResponse::NotFound().json(json!({ "status": 404, "success": false, "error": "Comment not found" })),
+        Err(response) => return response,
+    };
+    if comment.author != account.id {
+        return HttpResponse::Forbidden().json(json!({ "status": 403, "success": false, "error": "You did not write this comment." }));
+    }
+    if skin.comments_locked {
+        return HttpResponse::Forbidden().json(json!({ "status": 403, "success": false, "error": "Comments are locked on this skin." }));
+    }
+    let text = match check_comment(&params.text) {
+        Ok(text) => text,
+        Err(error) => return HttpResponse::BadRequest().json(json!({ "status": 400, "success": false, "error": error })),
+    };
+
+    let comments: Collection<CommentCollection> = client.database("ouja_skins").collection("comments");
+    let edited = DateTime::now();
+    match comments.update_one(doc! { "id": &comment.id }, doc! { "$set": { "text": &text, "edited": edited } }, None).await {
+        Ok(_result) => {
+            let comment = CommentCollection { text, edited: Some(edited), ..comment };
+            let usernames = HashMap::from([(account.id, account.username)]);
+            HttpResponse::Ok().json(json!({ "status": 200, "success": true, "comment": respond_comment(&comment, &usernames) }))
+        },
+        Err(err) => HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    }
+}
+
+// Authors can delete their comments, skin owners and moderators any comment on the skin.
+#[delete("/{id}/comments/{comment}")]
+pub async fn delete_comment(client: web::Data<Client>, path: web::Path<(String, String)>, req: HttpRequest) -> HttpResponse {
+    let (id, comment_id) = path.into_inner();
+    let account = match authenticate(&client, &req).await {
+        Ok(account) => account,
+        Err(response) => return response,
+    };
+    let skin = match find_skin(&client, &id).await {
+        Ok(skin) => skin,
+        Err(response) => return response,
+    };
+    let comment = match find_comment(&client, &id, &comment_id).await {
+        Ok(comment) if !comment.deleted => comment,
+        Ok(_deleted) => return HttpResponse::NotFound().json(json!({ "status": 404, "success": false, "error": "Comment not found" })),
+        Err(response) => return response,
+    };
+    if comment.author != account.id && skin.owner != account.id && !account.moderator {
+        return HttpResponse::Forbidden().json(json!({ "status": 403, "success": false, "error": "You cannot delete this comment." }));
+    }
+
+    match remove_comment(&client, &comment).await {
+        Ok(()) => HttpResponse::Ok().json(json!({ "status": 200, "success": true })),
+        Err(err) => HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    }
+}
+
+async fn set_comments_locked(client: &Client, id: &str, req: &HttpRequest, locked: bool) -> HttpResponse {
+    let account = match authenticate(client, req).await {
+        Ok(account) => account,
+        Err(response) => return response,
+    };
+    let skin = match find_skin(client, id).await {
+        Ok(skin) => skin,
+        Err(response) => return response,
+    };
+    if skin.owner != account.id && !account.moderator {
+        return HttpResponse::Forbidden().json(json!({ "status": 403, "success": false, "error": "You do not own this skin." }));
+    }
+
+    let skins: Collection<SkinCollection> = client.database("ouja_skins").collection("skins");
+    match skins.update_one(doc! { "id": id }, doc! { "$set": { "comments_locked": locked } }, None).await {
+        Ok(_result) => HttpResponse::Ok().json(json!({ "status": 200, "success": true, "locked": locked })),
+        Err(err) => HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    }
+}
+
+#[put("/{id}/comments/lock")]
+pub async fn lock_comments(client: web::Data<Client>, id: web::Path<String>, req: HttpRequest) -> HttpResponse {
+    set_comments_locked(&client, &id.into_inner(), &req, true).await
+}
+
+#[delete("/{id}/comments/lock")]
+pub async fn unlock_comments(client: web::Data<Client>, id: web::Path<String>, req: HttpRequest) -> HttpResponse {
+    set_comments_locked(&client, &id.into_inner(), &req, false).await
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ReportCommentParams {
+    reason: Option<String>,
+}
+
+// Puts a comment in the moderation queue. Reporting the same comment twice does nothing.
+#[post("/{id}/comments/{comment}/report")]
+pub async fn report_comment(client: web::Data<Client>, path: web::Path<(String, String)>, req: HttpRequest, params: web::Form<ReportCommentParams>) -> HttpResponse {
+    let (id, comment_id) = path.into_inner();
+    let account = match authenticate(&client, &req).await {
+        Ok(account) => account,
+        Err(response) => return response,
+    };
+    let comment = match find_comment(&client, &id, &comment_id).await {
+        Ok(comment) if !comment.deleted => comment,
+        Ok(_deleted) => return HttpResponse::NotFound().json(json!({ "status": 404, "success": false, "error": "Comment not found" })),
+        Err(response) => return response,
+    };
+    if comment.author == account.id {
+        return HttpResponse::BadRequest().json(json!({ "status": 400, "success": false, "error": "You cannot report your own comment." }));
+    }
+    let reason = params.reason.as_deref().unwrap_or_default().trim().to_string();
+    if let Err(error) = check_text("Reason", &reason) {
+        return HttpResponse::BadRequest().json(json!({ "status": 400, "success": false, "error": error }));
+    }
+
+    let report = ReportCollection {
+        id: Uuid::new_v4().to_string(),
+        date: DateTime::now(),
+        kind: ReportKind::Comment,
+        target: comment.id,
+        skin: id,
+        reporter: account.id,
+        reason,
+        resolution: None,
+        moderator: None,
+        resolved: None,
+    };
+    let reports: Collection<ReportCollection> = client.database("ouja_skins").collection("reports");
+    match reports.insert_one(&report, None).await {
+        Ok(_result) => HttpResponse::Ok().json(json!({ "status": 200, "success": true })),
+        Err(err) if is_duplicate_key(&err) => HttpResponse::Ok().json(json!({ "status": 200, "success": true })),
+        Err(err) => HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    }
+}
@@ -0,0 +1,341 @@
+use actix_web::{delete, get, put, web, HttpRequest, HttpResponse};
+use bson::{doc, Document};
+use futures_util::stream::StreamExt;
+use mongodb::{bson::DateTime, options::FindOptions, Client, Collection};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{
+    cursor::Cursor,
+    models::{Accounts, BlockCollection, FollowCollection, NotificationKind, TextureKind},
+    notifications::{spawn_notify, Notice},
+    util::{authenticate, escape_regex, find_usernames, is_duplicate_key, session_account},
+};
+
+use super::skins::{visible_filter, RespondSkin};
+
+// How many followers and followed accounts the profile lists, the rest is paginated.
+const PROFILE_FOLLOWS: i64 = 20;
+
+fn follows(client: &Client) -> Collection<FollowCollection> {
+    client.database("ouja_skins").collection("follows")
+}
+
+fn blocks(client: &Client) -> Collection<BlockCollection> {
+    client.database("ouja_skins").collection("blocks")
+}
+
+// Accounts on either side of a block with `account`.
+async fn blocked_accounts(client: &Client, account: &str) -> mongodb::error::Result<Vec<String>> {
+    let mut ids = Vec::new();
+    for (field, other) in [("blocker", "blocked"), ("blocked", "blocker")] {
+        let found = blocks(client).distinct(other, doc! { field: account }, None).await?;
+        ids.extend(found.into_iter().filter_map(|id| id.as_str().map(str::to_string)));
+    }
+    Ok(ids)
+}
+
+async fn find_account(client: &Client, username: &str) -> Result<Accounts, HttpResponse> {
+    let accounts: Collection<Accounts> = client.database("ouja_skins").collection("accounts");
+    match accounts.find_one(doc! { "username": { "$regex": format!("^{}$", escape_regex(username)), "$options": "i" } }, None).await {
+        Ok(Some(account)) => Ok(account),
+        Ok(None) => Err(HttpResponse::NotFound().json(json!({ "status": 404, "success": false, "error": "User not found" }))),
+        Err(err) => Err(HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() }))),
+    }
+}
+
+// The uploads a feed shows, only skins that are listed to other people and whose owner is
+// neither suspended nor on either side of a block with the viewer (`excluded`).
+fn feed_filter(owners: &[String], excluded: &[String]) -> Document {
+    let mut filter = visible_filter(TextureKind::Skin);
+    filter.insert("owner", doc! { "$in": owners, "$nin": excluded });
+    filter
+}
+
+// Lists one side of the follow relation of an account, most recent first.
+// `field` is the side the account is on, `other` the side that gets listed.
+async fn list_follows(
+    client: &Client,
+    account: &str,
+    field: &str,
+    other: &str,
+    limit: i64,
+    cursor: Option<&str>,
+) -> Result<(Vec<serde_json::Value>, u64, Option<String>), HttpResponse> {
+    let filter = doc! { field: account };
+    let total = match follows(client).count_documents(filter.clone(), None).await {
+        Ok(total) => total,
+        Err(err) => return Err(HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() }))),
+    };
+
+    let mut page_filter = filter;
+    match cursor.map(Cursor::decode) {
+        None => {},
+        Some(Some(Cursor::After { sort, date, id })) if sort == "followed" => {
+            let date = DateTime::from_millis(date);
+            page_filter.insert("$or", vec![
+                doc! { "date": { "$lt": date } },
+                doc! { "date": date, other: { "$lt": id } },
+            ]);
+        },
+        Some(_) => return Err(HttpResponse::BadRequest().json(json!({ "status": 400, "success": false, "error": "Invalid cursor!" }))),
+    }
+
+    let options = FindOptions::builder().sort(doc! { "date": -1, other: -1 }).limit(limit + 1).build();
+    let mut page: Vec<FollowCollection> = Vec::new();
+    let mut found = match follows(client).find(page_filter, options).await {
+        Ok(found) => found,
+        Err(err) => return Err(HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() }))),
+    };
+    while let Some(follow) = found.next().await {
+        match follow {
+            Ok(follow) => page.push(follow),
+            Err(err) => return Err(HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() }))),
+        }
+    }
+
+    let listed = |follow: &FollowCollection| if other == "follower" { follow.follower.clone() } else { follow.followee.clone() };
+    // One extra follow is fetched to know whether there is a next page.
+    let mut next_cursor = None;
+    if page.len() as i64 > limit {
+        page.truncate(limit as usize);
+        if let Some(last) = page.last() {
+            next_cursor = Some(Cursor::after("followed", last.date, &listed(last)).encode());
+        }
+    }
+
+    let ids: Vec<String> = page.iter().map(listed).collect();
+    let usernames = match find_usernames(client, &ids.iter().collect::<Vec<&String>>()).await {
+        Ok(usernames) => usernames,
+        Err(err) => return Err(HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() }))),
+    };
+    let accounts = page
+        .iter()
+        .filter_map(|follow| {
+            let id = listed(follow);
+            let username = usernames.get(&id)?;
+            Some(json!({ "id": id, "username": username, "date": follow.date }))
+        })
+        .collect();
+    Ok((accounts, total, next_cursor))
+}
+
+// The follower and following part of a public profile.
+pub async fn follow_summary(client: &Client, account: &str) -> Result<serde_json::Value, HttpResponse> {
+    let (followers, follower_count, _next) = list_follows(client, account, "followee", "follower", PROFILE_FOLLOWS, None).await?;
+    let (following, following_count, _next) = list_follows(client, account, "follower", "followee", PROFILE_FOLLOWS, None).await?;
+    Ok(json!({
+        "followers": { "count": follower_count, "accounts": followers },
+        "following": { "count": following_count, "accounts": following }
+    }))
+}
+
+#[put("/{username}/follow")]
+pub async fn follow_user(client: web::Data<Client>, username: web::Path<String>, req: HttpRequest) -> HttpResponse {
+    let account = match authenticate(&client, &req).await {
+        Ok(account) => account,
+        Err(response) => return response,
+    };
+    let followee = match find_account(&client, &username.into_inner()).await {
+        Ok(followee) => followee,
+        Err(response) => return response,
+    };
+    if followee.id == account.id {
+        return HttpResponse::BadRequest().json(json!({ "status": 400, "success": false, "error": "You cannot follow yourself." }));
+    }
+    match blocked_accounts(&client, &account.id).await {
+        Ok(blocked) if blocked.contains(&followee.id) => {
+            return HttpResponse::Forbidden().json(json!({ "status": 403, "success": false, "error": "You cannot follow this user." }));
+        },
+        Ok(_blocked) => {},
+        Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    }
+
+    let follow = FollowCollection { follower: account.id, followee: followee.id, date: DateTime::now() };
+    match follows(&client).insert_one(&follow, None).await {
+        Ok(_result) => {
+            spawn_notify(&client, &follow.followee, Notice::new(NotificationKind::Follow).actor(&follow.follower));
+            HttpResponse::Ok().json(json!({ "status": 200, "success": true, "following": true }))
+        },
+        Err(err) if is_duplicate_key(&err) => HttpResponse::Ok().json(json!({ "status": 200, "success": true, "following": true })),
+        Err(err) => HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    }
+}
+
+#[delete("/{username}/follow")]
+pub async fn unfollow_user(client: web::Data<Client>, username: web::Path<String>, req: HttpRequest) -> HttpResponse {
+    let account = match authenticate(&client, &req).await {
+        Ok(account) => account,
+        Err(response) => return response,
+    };
+    let followee = match find_account(&client, &username.into_inner()).await {
+        Ok(followee) => followee,
+        Err(response) => return response,
+    };
+    match follows(&client).delete_one(doc! { "follower": &account.id, "followee": &followee.id }, None).await {
+        Ok(_result) => HttpResponse::Ok().json(json!({ "status": 200, "success": true, "following": false })),
+        Err(err) => HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    }
+}
+
+#[put("/{username}/block")]
+pub async fn block_user(client: web::Data<Client>, username: web::Path<String>, req: HttpRequest) -> HttpResponse {
+    let account = match authenticate(&client, &req).await {
+        Ok(account) => account,
+        Err(response) => return response,
+    };
+    let blocked = match find_account(&client, &username.into_inner()).await {
+        Ok(blocked) => blocked,
+        Err(response) => return response,
+    };
+    if blocked.id == account.id {
+        return HttpResponse::BadRequest().json(json!({ "status": 400, "success": false, "error": "You cannot block yourself." }));
+    }
+
+    let block = BlockCollection { blocker: account.id, blocked: blocked.id, date: DateTime::now() };
+    match blocks(&client).insert_one(&block, None).await {
+        Ok(_result) => {},
+        Err(err) if is_duplicate_key(&err) => {},
+        Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    }
+    let follows_between = doc! { "$or": [
+        { "follower": &block.blocker, "followee": &block.blocked },
+        { "follower": &block.blocked, "followee": &block.blocker },
+    ] };
+    match follows(&client).delete_many(follows_between, None).await {
+        Ok(_result) => HttpResponse::Ok().json(json!({ "status": 200, "success": true, "blocked": true })),
+        Err(err) => HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    }
+}
+
+#[delete("/{username}/block")]
+pub async fn unblock_user(client: web::Data<Client>, username: web::Path<String>, req: HttpRequest) -> HttpResponse {
+    let account = match authenticate(&client, &req).await {
+        Ok(account) => account,
+        Err(response) => return response,
+    };
+    let blocked = match find_account(&client, &username.into_inner()).await {
+        Ok(blocked) => blocked,
+        Err(response) => return response,
+    };
+    match blocks(&client).delete_one(doc! { "blocker": &account.id, "blocked": &blocked.id }, None).await {
+        Ok(_result) => HttpResponse::Ok().json(json!({ "status": 200, "success": true, "blocked": false })),
+        Err(err) => HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct FollowsParams {
+    limit: Option<i64>,
+    cursor: Option<String>,
+}
+
+#[get("/{username}/followers")]
+pub async fn get_followers(client: web::Data<Client>, username: web::Path<String>, params: web::Query<FollowsParams>) -> HttpResponse {
+    let account = match find_account(&client, &username.into_inner()).await {
+        Ok(account) => account,
+        Err(response) => return response,
+    };
+    let limit = params.limit.unwrap_or(24).clamp(1, 100);
+    match list_follows(&client, &account.id, "followee", "follower", limit, params.cursor.as_deref()).await {
+        Ok((accounts, total, next_cursor)) => {
+            HttpResponse::Ok().json(json!({ "status": 200, "success": true, "followers": accounts, "total": total, "next_cursor": next_cursor }))
+        },
+        Err(response) => response,
+    }
+}
+
+#[get("/{username}/following")]
+pub async fn get_following(client: web::Data<Client>, username: web::Path<String>, params: web::Query<FollowsParams>) -> HttpResponse {
+    let account = match find_account(&client, &username.into_inner()).await {
+        Ok(account) => account,
+        Err(response) => return response,
+    };
+    let limit = params.limit.unwrap_or(24).clamp(1, 100);
+    match list_follows(&client, &account.id, "follower", "followee", limit, params.cursor.as_deref()).await {
+        Ok((accounts, total, next_cursor)) => {
+            HttpResponse::Ok().json(json!({ "status": 200, "success": true, "following": accounts, "total": total, "next_cursor": next_cursor }))
+        },
+        Err(response) => response,
+    }
+}
+
+// Recent uploads of the accounts the requester follows, newest first.
+#[get("")]
+pub async fn get_feed(client: web::Data<Client>, req: HttpRequest, params: web::Query<FollowsParams>) -> HttpResponse {
+    let account = match session_account(&client, &req).await {
+        Ok(account) => account,
+        Err(response) => return response,
+    };
+    let limit = params.limit.unwrap_or(24).clamp(1, 100);
+
+    let followees = match follows(&client).distinct("followee", doc! { "follower": &account.id }, None).await {
+        Ok(followees) => followees.into_iter().filter_map(|id| id.as_str().map(str::to_string)).collect::<Vec<String>>(),
+        Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    };
+
+    let mut excluded = match blocked_accounts(&client, &account.id).await {
+        Ok(excluded) => excluded,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    };
+    let accounts: Collection<Accounts> = client.database("ouja_skins").collection("accounts");
+    match accounts.distinct("id", doc! { "id": { "$in": &followees }, "suspended": true }, None).await {
+        Ok(suspended) => excluded.extend(suspended.into_iter().filter_map(|id| id.as_str().map(str::to_string))),
+        Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    }
+
+    let mut filter = feed_filter(&followees, &excluded);
+    match params.cursor.as_deref().map(Cursor::decode) {
+        None => {},
+        Some(Some(Cursor::After { sort, date, id })) if sort == "newest" => {
+            let date = DateTime::from_millis(date);
+            filter.insert("$or", vec![
+                doc! { "date": { "$lt": date } },
+                doc! { "date": date, "id": { "$lt": id } },
+            ]);
+        },
+        Some(_) => return HttpResponse::BadRequest().json(json!({ "status": 400, "success": false, "error": "Invalid cursor!" })),
+    }
+
+    let skins: Collection<RespondSkin> = client.database("ouja_skins").collection("skins");
+    let options = FindOptions::builder().sort(doc! { "date": -1, "id": -1 }).limit(limit + 1).build();
+    let mut cursor = match skins.find(filter, options).await {
+        Ok(cursor) => cursor,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    };
+    let mut page: Vec<RespondSkin> = Vec::new();
+    while let Some(skin) = cursor.next().await {
+        match skin {
+            Ok(skin) => page.push(skin),
+            Err(err) => {
+                println!("{:?} - collecting skins", err);
+                return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() }));
+            }
+        }
+    }
+
+    // One extra skin is fetched to know whether there is a next page.
+    let mut next_cursor = None;
+    if page.len() as i64 > limit {
+        page.truncate(limit as usize);
+        if let Some(last) = page.last() {
+            next_cursor = Some(Cursor::after("newest", last.date, &last.id).encode());
+        }
+    }
+
+    let owners: Vec<&String> = page.iter().map(|skin| &skin.owner).collect();
+    let usernames = match find_usernames(&client, &owners).await {
+        Ok(usernames) => usernames,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    };
+    let results: Vec<serde_json::Value> = page
+        .iter()
+        .map(|skin| {
+            let mut response = json!(skin);
+            response["owner_name"] = json!(usernames.get(&skin.owner));
+            response
+        })
+        .collect();
+
+    HttpResponse::Ok().json(json!({ "status": 200, "success": true, "skins": results, "next_cursor": next_cursor }))
+}
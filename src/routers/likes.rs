@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+
+use actix_web::{delete, get, put, web, HttpRequest, HttpResponse};
+use bson::doc;
+use futures_util::stream::StreamExt;
+use mongodb::{
+    bson::DateTime,
+    options::{FindOneAndUpdateOptions, FindOptions, ReturnDocument},
+    Client, Collection,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{
+    cursor::Cursor,
+    models::{Accounts, LikeCollection, NotificationKind, SkinCollection, TextureKind},
+    notifications::{spawn_notify, Notice},
+    util::{authenticate, escape_regex, is_duplicate_key},
+};
+
+use super::skins::RespondSkin;
+
+// Moves the like counter of a skin and returns the new value, or None if the skin is gone.
+async fn increment_likes(client: &Client, id: &str, by: i64) -> mongodb::error::Result<Option<i64>> {
+    let skins: Collection<SkinCollection> = client.database("ouja_skins").collection("skins");
+    let options = FindOneAndUpdateOptions::builder().return_document(ReturnDocument::After).build();
+    let skin = skins
+        .find_one_and_update(doc! { "id": id, "kind": TextureKind::Skin.filter() }, doc! { "$inc": { "likes": by } }, options)
+        .await?;
+    Ok(skin.map(|skin| skin.likes))
+}
+
+// Called when a texture is deleted so no like points to it anymore.
+pub async fn remove_skin_likes(client: &Client, id: &str) -> mongodb::error::Result<()> {
+    let likes: Collection<LikeCollection> = client.database("ouja_skins").collection("likes");
+    likes.delete_many(doc! { "skin": id }, None).await?;
+    Ok(())
+}
+
+#[put("/{id}/like")]
+pub async fn like_skin(client: web::Data<Client>, id: web::Path<String>, req: HttpRequest) -> HttpResponse {
+    let id = id.into_inner();
+    let account = match authenticate(&client, &req).await {
+        Ok(account) => account,
+        Err(response) => return response,
+    };
+    let skins: Collection<SkinCollection> = client.database("ouja_skins").collection("skins");
+    let likes: Collection<LikeCollection> = client.database("ouja_skins").collection("likes");
+    let skin = match skins.find_one(doc! { "id": &id, "kind": TextureKind::Skin.filter() }, None).await {
+        Ok(Some(skin)) => skin,
+        Ok(None) => return HttpResponse::NotFound().json(json!({ "status": 404, "success": false, "error": "Skin not found" })),
+        Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    };
+
+    // The unique index on (account, skin) decides who wins when the same like arrives
+    // twice, only the request that inserted it moves the counter.
+    let like = LikeCollection { account: account.id.clone(), skin: id.clone(), date: DateTime::now() };
+    match likes.insert_one(&like, None).await {
+        Ok(_result) => {},
+        Err(err) if is_duplicate_key(&err) => {
+            return HttpResponse::Ok().json(json!({ "status": 200, "success": true, "liked": true, "likes": skin.likes }));
+        },
+        Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    }
+
+    match increment_likes(&client, &id, 1).await {
+        Ok(Some(count)) => {
+            spawn_notify(&client, &skin.owner, Notice::new(NotificationKind::Like).actor(&account.id).skin(&id));
+            HttpResponse::Ok().json(json!({ "status": 200, "success": true, "liked": true, "likes": count }))
+        },
+        Ok(None) => {
+            // The skin was deleted in the meantime, don't leave the like behind.
+            if let Err(err) = remove_skin_likes(&client, &id).await {
+                println!("{:?} - Removing likes", err);
+            }
+            HttpResponse::NotFound().json(json!({ "status": 404, "success": false, "error": "Skin not found" }))
+        },
+        Err(err) => HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    }
+}
+
+#[delete("/{id}/like")]
+pub async fn unlike_skin(client: web::Data<Client>, id: web::Path<String>, req: HttpRequest) -> HttpResponse {
+    let id = id.into_inner();
+    let account = match authenticate(&client, &req).await {
+        Ok(account) => account,
+        Err(response) => return response,
+    };
+    let likes: Collection<LikeCollection> = client.database("ouja_skins").collection("likes");
+    let deleted = match likes.delete_one(doc! { "account": &account.id, "skin": &id }, None).await {
+        Ok(result) => result.deleted_count,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    };
+
+    let count = if deleted > 0 {
+        increment_likes(&client, &id, -1).await
+    } else {
+        let skins: Collection<SkinCollection> = client.database("ouja_skins").collection("skins");
+        skins
+            .find_one(doc! { "id": &id, "kind": TextureKind::Skin.filter() }, None)
+            .await
+            .map(|skin| skin.map(|skin| skin.likes))
+    };
+    match count {
+        Ok(Some(count)) => HttpResponse::Ok().json(json!({ "status": 200, "success": true, "liked": false, "likes": count })),
+        Ok(None) => HttpResponse::NotFound().json(json!({ "status": 404, "success": false, "error": "Skin not found" })),
+        Err(err) => HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LikesParams {
+    limit: Option<i64>,
+    cursor: Option<String>,
+}
+
+#[get("/{username}/likes")]
+pub async fn get_user_likes(client: web::Data<Client>, username: web::Path<String>, params: web::Query<LikesParams>) -> HttpResponse {
+    let username = username.into_inner();
+    let limit = params.limit.unwrap_or(24).clamp(1, 100);
+    let accounts: Collection<Accounts> = client.database("ouja_skins").collection("accounts");
+    let likes: Collection<LikeCollection> = client.database("ouja_skins").collection("likes");
+    let skins: Collection<RespondSkin> = client.database("ouja_skins").collection("skins");
+
+    let account = match accounts.find_one(doc! { "username": { "$regex": format!("^{}$", escape_regex(&username)), "$options": "i" } }, None).await {
+        Ok(Some(account)) => account,
+        Ok(None) => return HttpResponse::NotFound().json(json!({ "status": 404, "success": false, "error": "User not found" })),
+        Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    };
+
+    let filter = doc! { "account": &account.id };
+    let total = match likes.count_documents(filter.clone(), None).await {
+        Ok(total) => total,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    };
+
+    let mut page_filter = filter;
+    match params.cursor.as_deref().map(Cursor::decode) {
+        None => {},
+        Some(Some(Cursor::After { sort, date, id })) if sort == "liked" => {
+            let date = DateTime::from_millis(date);
+            page_filter.insert("$or", vec![
+                doc! { "date": { "$lt": date } },
+                doc! { "date": date, "skin": { "$lt": id } },
+            ]);
+        },
+        Some(_) => return HttpResponse::BadRequest().json(json!({ "status": 400, "success": false, "error": "Invalid cursor!" })),
+    }
+
+    let options = FindOptions::builder().sort(doc! { "date": -1, "skin": -1 }).limit(limit + 1).build();
+    let mut cursor = match likes.find(page_filter, options).await {
+        Ok(cursor) => cursor,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    };
+    let mut page: Vec<LikeCollection> = Vec::new();
+    while let Some(like) = cursor.next().await {
+        match like {
+            Ok(like) => page.push(like),
+            Err(err) => {
+                println!("{:?} - collecting likes", err);
+                return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() }));
+            }
+        }
+    }
+
+    // One extra like is fetched to know whether there is a next page.
+    let mut next_cursor = None;
+    if page.len() as i64 > limit {
+        page.truncate(limit as usize);
+        if let Some(last) = page.last() {
+            next_cursor = Some(Cursor::after("liked", last.date, &last.skin).encode());
+        }
+    }
+
+    let ids: Vec<&String> = page.iter().map(|like| &like.skin).collect();
+    let mut found: HashMap<String, RespondSkin> = HashMap::new();
+    match skins.find(doc! { "id": { "$in": ids }, "kind": TextureKind::Skin.filter() }, None).await {
+        Ok(mut cursor) => {
+            while let Some(skin) = cursor.next().await {
+                match skin {
+                    Ok(skin) => {
+                        found.insert(skin.id.clone(), skin);
+                    },
+                    Err(err) => {
+                        println!("{:?} - collecting skins", err);
+                        return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() }));
+                    }
+                }
+            }
+        },
+        Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    }
+
+    let results: Vec<serde_json::Value> = page
+        .iter()
+        .filter_map(|like| {
+            let mut skin = json!(found.get(&like.skin)?);
+            skin["liked_at"] = json!(like.date);
+            Some(skin)
+        })
+        .collect();
+
+    HttpResponse::Ok().json(json!({ "status": 200, "success": true, "skins": results, "total": total, "next_cursor": next_cursor }))
+}
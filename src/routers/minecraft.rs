@@ -0,0 +1,125 @@
+use actix_web::{delete, post, web, HttpRequest, HttpResponse};
+use bson::doc;
+use mongodb::{bson::DateTime, options::ReplaceOptions, Client, Collection};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{
+    minecraft::{fetch_skin, find_player, has_pattern, issue_pattern, PATTERN_PIXELS},
+    models::{Accounts, MinecraftClaim, MinecraftLink},
+    util::{authenticate, offline_uuid},
+};
+
+// How long the pattern can be put on the Minecraft skin before a new one has to be asked for.
+const CLAIM_MINUTES: i64 = 60;
+
+fn claims(client: &Client) -> Collection<MinecraftClaim> {
+    client.database("ouja_skins").collection("minecraft_claims")
+}
+
+fn respond_pattern(claim: &MinecraftClaim) -> serde_json::Value {
+    let pixels: Vec<serde_json::Value> = PATTERN_PIXELS
+        .iter()
+        .zip(&claim.pattern)
+        .map(|(&(x, y), color)| json!({ "x": x, "y": y, "color": format!("#{}", color) }))
+        .collect();
+    json!({ "uuid": claim.uuid, "name": claim.name, "pattern": pixels, "expires": claim.expires })
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ClaimParams {
+    player: String,
+}
+
+// Starts claiming a Minecraft identity by name or UUID. The answer holds the pixels the skin
+// of that Minecraft account has to show, on 64x64 coordinates, before `verify` links it.
+#[post("/minecraft")]
+pub async fn claim_minecraft(client: web::Data<Client>, req: HttpRequest, params: web::Form<ClaimParams>) -> HttpResponse {
+    let account = match authenticate(&client, &req).await {
+        Ok(account) => account,
+        Err(response) => return response,
+    };
+    let (uuid, name) = match find_player(params.player.trim()).await {
+        Ok(Some(player)) => player,
+        Ok(None) => return HttpResponse::NotFound().json(json!({ "status": 404, "success": false, "error": "Minecraft player not found" })),
+        Err(err) => {
+            println!("{} - looking up minecraft player", err);
+            return HttpResponse::BadGateway().json(json!({ "status": 502, "success": false, "error": "Could not reach the Minecraft profile API." }));
+        },
+    };
+    if account.minecraft.as_ref().map(|link| &link.uuid) == Some(&uuid) {
+        return HttpResponse::BadRequest().json(json!({ "status": 400, "success": false, "error": "This Minecraft account is already linked." }));
+    }
+
+    let created = DateTime::now();
+    let claim = MinecraftClaim {
+        account: account.id.clone(),
+        uuid,
+        name,
+        pattern: issue_pattern(),
+        created,
+        expires: DateTime::from_millis(created.timestamp_millis() + CLAIM_MINUTES * 60 * 1000),
+    };
+    // An account claims one identity at a time, a new claim replaces the previous one.
+    let options = ReplaceOptions::builder().upsert(true).build();
+    match claims(&client).replace_one(doc! { "account": &account.id }, &claim, options).await {
+        Ok(_result) => HttpResponse::Ok().json(json!({ "status": 200, "success": true, "claim": respond_pattern(&claim) })),
+        Err(err) => HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    }
+}
+
+// Links the claimed identity once its skin shows the pattern. Whoever proves ownership wins,
+// an account that had the identity linked before loses it.
+#[post("/minecraft/verify")]
+pub async fn verify_minecraft(client: web::Data<Client>, req: HttpRequest) -> HttpResponse {
+    let account = match authenticate(&client, &req).await {
+        Ok(account) => account,
+        Err(response) => return response,
+    };
+    let claim = match claims(&client).find_one(doc! { "account": &account.id, "expires": { "$gt": DateTime::now() } }, None).await {
+        Ok(Some(claim)) => claim,
+        Ok(None) => return HttpResponse::NotFound().json(json!({ "status": 404, "success": false, "error": "No pending Minecraft claim, start one first." })),
+        Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    };
+    let (name, skin) = match fetch_skin(&claim.uuid).await {
+        Ok(Some(found)) => found,
+        Ok(None) => return HttpResponse::BadRequest().json(json!({ "status": 400, "success": false, "error": "The Minecraft account has no skin yet." })),
+        Err(err) => {
+            println!("{} - fetching minecraft skin", err);
+            return HttpResponse::BadGateway().json(json!({ "status": 502, "success": false, "error": "Could not reach the Minecraft profile API." }));
+        },
+    };
+    if !has_pattern(&skin, &claim.pattern) {
+        return HttpResponse::BadRequest().json(json!({ "status": 400, "success": false, "error": "The Minecraft skin does not show the verification pattern yet." }));
+    }
+
+    let link = MinecraftLink { offline_uuid: offline_uuid(&name), uuid: claim.uuid, name, verified: DateTime::now() };
+    let accounts: Collection<Accounts> = client.database("ouja_skins").collection("accounts");
+    if let Err(err) = accounts.update_many(doc! { "minecraft.uuid": &link.uuid, "id": { "$ne": &account.id } }, doc! { "$set": { "minecraft": null } }, None).await {
+        return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() }));
+    }
+    let stored = match bson::to_bson(&link) {
+        Ok(stored) => stored,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    };
+    if let Err(err) = accounts.update_one(doc! { "id": &account.id }, doc! { "$set": { "minecraft": stored } }, None).await {
+        return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() }));
+    }
+    if let Err(err) = claims(&client).delete_one(doc! { "account": &account.id }, None).await {
+        println!("{:?} - removing minecraft claim", err);
+    }
+    HttpResponse::Ok().json(json!({ "status": 200, "success": true, "minecraft": link }))
+}
+
+#[delete("/minecraft")]
+pub async fn unlink_minecraft(client: web::Data<Client>, req: HttpRequest) -> HttpResponse {
+    let account = match authenticate(&client, &req).await {
+        Ok(account) => account,
+        Err(response) => return response,
+    };
+    let accounts: Collection<Accounts> = client.database("ouja_skins").collection("accounts");
+    match accounts.update_one(doc! { "id": &account.id }, doc! { "$set": { "minecraft": null } }, None).await {
+        Ok(_result) => HttpResponse::Ok().json(json!({ "status": 200, "success": true, "minecraft": null })),
+        Err(err) => HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    }
+}
@@ -1,8 +1,22 @@
 use actix_web::web;
 
 mod account;
+mod capes;
+mod collections;
+mod comments;
+mod follows;
+mod likes;
+mod minecraft;
+mod moderation;
+mod notifications;
+mod revisions;
+mod search;
 mod skins;
+mod stats;
+mod tags;
+mod textures;
 mod user;
+mod yggdrasil;
 
 pub fn v1(cfg: &mut web::ServiceConfig) {
     cfg.service(web::scope("v1").configure(v1_config));
@@ -12,12 +26,83 @@ pub fn v1_config(cfg: &mut web::ServiceConfig) {
     cfg.service(
         web::scope("user")
             .service(user::index)
-            .service(user::get_user_skins),
+            .service(user::get_user_skins)
+            .service(user::get_user_capes)
+            .service(likes::get_user_likes)
+            .service(collections::get_user_collections)
+            .service(follows::follow_user)
+            .service(follows::unfollow_user)
+            .service(follows::block_user)
+            .service(follows::unblock_user)
+            .service(follows::get_followers)
+            .service(follows::get_following)
+            .service(textures::get_user_textures),
     );
     cfg.service(
         web::scope("skins")
+            .service(search::search_skins)
             .service(skins::upload_skin)
-            .service(skins::get_skin),
+            .service(skins::get_skin)
+            .service(skins::get_skin_texture)
+            .service(skins::render_skin_3d)
+            .service(skins::get_suspected_copies)
+            .service(skins::update_skin)
+            .service(skins::delete_skin)
+            .service(likes::like_skin)
+            .service(likes::unlike_skin)
+            .service(stats::get_skin_stats)
+            .service(comments::get_comments)
+            .service(comments::create_comment)
+            .service(comments::lock_comments)
+            .service(comments::unlock_comments)
+            .service(comments::update_comment)
+            .service(comments::delete_comment)
+            .service(comments::report_comment)
+            .service(revisions::upload_revision)
+            .service(revisions::get_revisions)
+            .service(revisions::get_revision_texture)
+            .service(revisions::restore_revision),
+    );
+    cfg.service(
+        web::scope("capes")
+            .service(capes::upload_cape)
+            .service(capes::get_cape)
+            .service(capes::get_cape_texture)
+            .service(capes::update_cape)
+            .service(capes::delete_cape),
+    );
+    cfg.service(
+        web::scope("tags")
+            .service(tags::get_popular_tags)
+            .service(tags::get_categories)
+            .service(tags::get_category_skins)
+            .service(tags::get_tag_skins),
+    );
+    cfg.service(web::scope("feed").service(follows::get_feed));
+    cfg.service(
+        web::scope("notifications")
+            .service(notifications::get_notifications)
+            .service(notifications::mark_all_read)
+            .service(notifications::mark_read)
+            .service(notifications::get_preferences)
+            .service(notifications::update_preferences),
+    );
+    cfg.service(
+        web::scope("collections")
+            .service(collections::create_collection)
+            .service(collections::get_collection)
+            .service(collections::update_collection)
+            .service(collections::delete_collection)
+            .service(collections::add_collection_skin)
+            .service(collections::remove_collection_skin),
+    );
+    cfg.service(
+        web::scope("moderation")
+            .service(moderation::get_reports)
+            .service(moderation::resolve_report)
+            .service(moderation::resolve_copy)
+            .service(moderation::suspend_account)
+            .service(moderation::unsuspend_account),
     );
     cfg.service(
         web::scope("account")
@@ -25,6 +110,37 @@ pub fn v1_config(cfg: &mut web::ServiceConfig) {
             .service(account::login)
             .service(account::register)
             .service(account::update_user)
-            .service(account::update_email),
+            .service(account::update_email)
+            .service(account::update_active_skin)
+            .service(account::update_cape)
+            .service(account::update_profile_picture)
+            .service(minecraft::claim_minecraft)
+            .service(minecraft::verify_minecraft)
+            .service(minecraft::unlink_minecraft),
+    );
+    cfg.service(textures::get_texture_by_hash);
+    cfg.service(web::scope("profiles").service(textures::lookup_profiles));
+    // Root for the CustomSkinLoader mod on offline mode servers.
+    cfg.service(
+        web::scope("csl")
+            .service(textures::get_texture_by_hash)
+            .service(textures::get_customskinloader_profile),
+    );
+    // Launchers are pointed at this scope as their API root.
+    cfg.service(
+        web::scope("yggdrasil")
+            .route("", web::get().to(yggdrasil::api_metadata))
+            .route("/", web::get().to(yggdrasil::api_metadata))
+            .service(yggdrasil::authenticate)
+            .service(yggdrasil::refresh)
+            .service(yggdrasil::validate)
+            .service(yggdrasil::invalidate)
+            .service(yggdrasil::signout)
+            .service(yggdrasil::join_server)
+            .service(yggdrasil::has_joined)
+            .service(yggdrasil::get_profile)
+            .service(yggdrasil::lookup_profiles)
+            .service(yggdrasil::get_public_keys),
     );
+    cfg.service(web::scope("avatars").service(account::get_avatar));
 }
@@ -0,0 +1,261 @@
+use std::collections::HashMap;
+
+use actix_web::{delete, get, post, put, web, HttpRequest, HttpResponse};
+use bson::{doc, Bson};
+use futures_util::stream::StreamExt;
+use mongodb::{bson::DateTime, options::FindOptions, Client, Collection};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{
+    cursor::Cursor,
+    models::{Accounts, CommentCollection, NotificationKind, ReportCollection, ReportResolution, SkinCollection},
+    notifications::{spawn_notify, Notice},
+    util::{authenticate, escape_regex, find_usernames, session_account},
+};
+
+use super::{
+    comments::{remove_comment, respond_comment},
+    skins::remove_texture,
+};
+
+// Reading the queue only needs the session, acting on it also the CSRF token.
+async fn moderator_account(client: &Client, req: &HttpRequest, csrf: bool) -> Result<Accounts, HttpResponse> {
+    let account = if csrf { authenticate(client, req).await? } else { session_account(client, req).await? };
+    if !account.moderator {
+        return Err(HttpResponse::Forbidden().json(json!({ "status": 403, "success": false, "error": "Only moderators can do this." })));
+    }
+    Ok(account)
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ReportsParams {
+    limit: Option<i64>,
+    cursor: Option<String>,
+}
+
+// The moderation queue: open reports, oldest first, with what they are about.
+#[get("/reports")]
+pub async fn get_reports(client: web::Data<Client>, req: HttpRequest, params: web::Query<ReportsParams>) -> HttpResponse {
+    if let Err(response) = moderator_account(&client, &req, false).await {
+        return response;
+    }
+    let limit = params.limit.unwrap_or(24).clamp(1, 100);
+    let reports: Collection<ReportCollection> = client.database("ouja_skins").collection("reports");
+    let comments: Collection<CommentCollection> = client.database("ouja_skins").collection("comments");
+
+    let filter = doc! { "resolution": null };
+    let total = match reports.count_documents(filter.clone(), None).await {
+        Ok(total) => total,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    };
+
+    let mut page_filter = filter;
+    match params.cursor.as_deref().map(Cursor::decode) {
+        None => {},
+        Some(Some(Cursor::After { sort, date, id })) if sort == "oldest" => {
+            let date = DateTime::from_millis(date);
+            page_filter.insert("$or", vec![
+                doc! { "date": { "$gt": date } },
+                doc! { "date": date, "id": { "$gt": id } },
+            ]);
+        },
+        Some(_) => return HttpResponse::BadRequest().json(json!({ "status": 400, "success": false, "error": "Invalid cursor!" })),
+    }
+
+    let options = FindOptions::builder().sort(doc! { "date": 1, "id": 1 }).limit(limit + 1).build();
+    let mut cursor = match reports.find(page_filter, options).await {
+        Ok(cursor) => cursor,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    };
+    let mut page: Vec<ReportCollection> = Vec::new();
+    while let Some(report) = cursor.next().await {
+        match report {
+            Ok(report) => page.push(report),
+            Err(err) => {
+                println!("{:?} - collecting reports", err);
+                return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() }));
+            }
+        }
+    }
+
+    // One extra report is fetched to know whether there is a next page.
+    let mut next_cursor = None;
+    if page.len() as i64 > limit {
+        page.truncate(limit as usize);
+        if let Some(last) = page.last() {
+            next_cursor = Some(Cursor::after("oldest", last.date, &last.id).encode());
+        }
+    }
+
+    let targets: Vec<&String> = page.iter().map(|report| &report.target).collect();
+    let mut found: HashMap<String, CommentCollection> = HashMap::new();
+    match comments.find(doc! { "id": { "$in": targets } }, None).await {
+        Ok(mut cursor) => {
+            while let Some(comment) = cursor.next().await {
+                match comment {
+                    Ok(comment) => {
+                        found.insert(comment.id.clone(), comment);
+                    },
+                    Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+                }
+            }
+        },
+        Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    }
+
+    let mut accounts: Vec<&String> = page.iter().map(|report| &report.reporter).collect();
+    accounts.extend(found.values().map(|comment| &comment.author));
+    let usernames = match find_usernames(&client, &accounts).await {
+        Ok(usernames) => usernames,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    };
+
+    let results: Vec<serde_json::Value> = page
+        .iter()
+        .map(|report| {
+            json!({
+                "id": report.id,
+                "date": report.date,
+                "kind": report.kind,
+                "skin": report.skin,
+                "reporter": report.reporter,
+                "reporter_name": usernames.get(&report.reporter),
+                "reason": report.reason,
+                "comment": found.get(&report.target).map(|comment| respond_comment(comment, &usernames))
+            })
+        })
+        .collect();
+
+    HttpResponse::Ok().json(json!({ "status": 200, "success": true, "reports": results, "total": total, "next_cursor": next_cursor }))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ResolveReportParams {
+    action: String,
+}
+
+// Dismisses a report or removes what it is about. Other open reports about the same
+// comment are resolved the same way.
+#[post("/reports/{id}/resolve")]
+pub async fn resolve_report(client: web::Data<Client>, id: web::Path<String>, req: HttpRequest, params: web::Form<ResolveReportParams>) -> HttpResponse {
+    let id = id.into_inner();
+    let account = match moderator_account(&client, &req, true).await {
+        Ok(account) => account,
+        Err(response) => return response,
+    };
+    let Some(resolution) = ReportResolution::parse(&params.action) else {
+        return HttpResponse::BadRequest().json(json!({ "status": 400, "success": false, "error": "Action must be dismiss or remove!" }));
+    };
+    let reports: Collection<ReportCollection> = client.database("ouja_skins").collection("reports");
+    let comments: Collection<CommentCollection> = client.database("ouja_skins").collection("comments");
+
+    let report = match reports.find_one(doc! { "id": &id }, None).await {
+        Ok(Some(report)) if report.resolution.is_none() => report,
+        Ok(Some(_resolved)) => return HttpResponse::Conflict().json(json!({ "status": 409, "success": false, "error": "Report is already resolved." })),
+        Ok(None) => return HttpResponse::NotFound().json(json!({ "status": 404, "success": false, "error": "Report not found" })),
+        Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    };
+
+    let reporters = match reports.distinct("reporter", doc! { "target": &report.target, "resolution": null }, None).await {
+        Ok(reporters) => reporters,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    };
+    let result = reports
+        .update_many(
+            doc! { "target": &report.target, "resolution": null },
+            doc! { "$set": { "resolution": resolution.name(), "moderator": &account.id, "resolved": DateTime::now() } },
+            None,
+        )
+        .await;
+    if let Err(err) = result {
+        return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() }));
+    }
+
+    if resolution == ReportResolution::Removed {
+        match comments.find_one(doc! { "id": &report.target, "deleted": false }, None).await {
+            Ok(Some(comment)) => {
+                if let Err(err) = remove_comment(&client, &comment).await {
+                    return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() }));
+                }
+                spawn_notify(&client, &comment.author, Notice::new(NotificationKind::Moderation).skin(&report.skin).detail("comment_removed"));
+            },
+            Ok(None) => {},
+            Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+        }
+    }
+
+    // Everyone who reported the comment learns what came of it.
+    for reporter in reporters.iter().filter_map(Bson::as_str) {
+        let notice = Notice::new(NotificationKind::Moderation).skin(&report.skin).comment(&report.target).detail(resolution.name());
+        spawn_notify(&client, reporter, notice);
+    }
+
+    HttpResponse::Ok().json(json!({ "status": 200, "success": true, "resolution": resolution }))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ResolveCopyParams {
+    action: String,
+}
+
+// Settles a texture flagged as a near duplicate. `clear` drops the flag so it shows up in
+// feeds and collections again, `confirm` removes it as a copy. A new revision is checked anew.
+#[post("/copies/{id}/resolve")]
+pub async fn resolve_copy(client: web::Data<Client>, id: web::Path<String>, req: HttpRequest, params: web::Form<ResolveCopyParams>) -> HttpResponse {
+    if let Err(response) = moderator_account(&client, &req, true).await {
+        return response;
+    }
+    let skins: Collection<SkinCollection> = client.database("ouja_skins").collection("skins");
+    let skin = match skins.find_one(doc! { "id": id.into_inner(), "suspected_copy_of.0": { "$exists": true } }, None).await {
+        Ok(Some(skin)) => skin,
+        Ok(None) => return HttpResponse::NotFound().json(json!({ "status": 404, "success": false, "error": "No flagged texture with this id." })),
+        Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    };
+
+    match params.action.as_str() {
+        "clear" => {
+            if let Err(err) = skins.update_one(doc! { "id": &skin.id }, doc! { "$set": { "suspected_copy_of": [] } }, None).await {
+                return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() }));
+            }
+        },
+        "confirm" => {
+            if let Err(err) = remove_texture(&client, &skin).await {
+                return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() }));
+            }
+            spawn_notify(&client, &skin.owner, Notice::new(NotificationKind::Moderation).skin(&skin.id).detail("copy_removed"));
+        },
+        _ => return HttpResponse::BadRequest().json(json!({ "status": 400, "success": false, "error": "Action must be clear or confirm!" })),
+    }
+
+    HttpResponse::Ok().json(json!({ "status": 200, "success": true, "action": params.action }))
+}
+
+// Suspends or lifts the suspension of an account, moderators cannot be suspended.
+async fn set_suspended(client: &Client, req: &HttpRequest, username: &str, suspended: bool) -> HttpResponse {
+    if let Err(response) = moderator_account(client, req, true).await {
+        return response;
+    }
+    let accounts: Collection<Accounts> = client.database("ouja_skins").collection("accounts");
+    let filter = doc! { "username": { "$regex": format!("^{}$", escape_regex(username)), "$options": "i" }, "moderator": { "$ne": true } };
+    let account = match accounts.find_one_and_update(filter, doc! { "$set": { "suspended": suspended } }, None).await {
+        Ok(Some(account)) => account,
+        Ok(None) => return HttpResponse::NotFound().json(json!({ "status": 404, "success": false, "error": "User not found" })),
+        Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    };
+    if account.suspended != suspended {
+        let detail = if suspended { "account_suspended" } else { "account_unsuspended" };
+        spawn_notify(client, &account.id, Notice::new(NotificationKind::Moderation).detail(detail));
+    }
+    HttpResponse::Ok().json(json!({ "status": 200, "success": true, "suspended": suspended }))
+}
+
+#[put("/accounts/{username}/suspension")]
+pub async fn suspend_account(client: web::Data<Client>, username: web::Path<String>, req: HttpRequest) -> HttpResponse {
+    set_suspended(&client, &req, &username, true).await
+}
+
+#[delete("/accounts/{username}/suspension")]
+pub async fn unsuspend_account(client: web::Data<Client>, username: web::Path<String>, req: HttpRequest) -> HttpResponse {
+    set_suspended(&client, &req, &username, false).await
+}
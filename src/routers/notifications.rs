@@ -0,0 +1,173 @@
+use actix_web::{get, patch, post, web, HttpRequest, HttpResponse};
+use bson::doc;
+use futures_util::stream::StreamExt;
+use mongodb::{bson::DateTime, options::FindOptions, Client, Collection};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{
+    cursor::Cursor,
+    models::{Accounts, NotificationCollection, NotificationKind},
+    util::{authenticate, find_usernames, session_account},
+};
+
+fn notifications(client: &Client) -> Collection<NotificationCollection> {
+    client.database("ouja_skins").collection("notifications")
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct NotificationsParams {
+    limit: Option<i64>,
+    cursor: Option<String>,
+    unread: Option<bool>,
+}
+
+#[get("")]
+pub async fn get_notifications(client: web::Data<Client>, req: HttpRequest, params: web::Query<NotificationsParams>) -> HttpResponse {
+    let account = match session_account(&client, &req).await {
+        Ok(account) => account,
+        Err(response) => return response,
+    };
+    let limit = params.limit.unwrap_or(24).clamp(1, 100);
+
+    let unread = match notifications(&client).count_documents(doc! { "recipient": &account.id, "read": false }, None).await {
+        Ok(unread) => unread,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    };
+
+    let mut filter = doc! { "recipient": &account.id };
+    if params.unread == Some(true) {
+        filter.insert("read", false);
+    }
+    match params.cursor.as_deref().map(Cursor::decode) {
+        None => {},
+        Some(Some(Cursor::After { sort, date, id })) if sort == "newest" => {
+            let date = DateTime::from_millis(date);
+            filter.insert("$or", vec![
+                doc! { "date": { "$lt": date } },
+                doc! { "date": date, "id": { "$lt": id } },
+            ]);
+        },
+        Some(_) => return HttpResponse::BadRequest().json(json!({ "status": 400, "success": false, "error": "Invalid cursor!" })),
+    }
+
+    let options = FindOptions::builder().sort(doc! { "date": -1, "id": -1 }).limit(limit + 1).build();
+    let mut cursor = match notifications(&client).find(filter, options).await {
+        Ok(cursor) => cursor,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    };
+    let mut page: Vec<NotificationCollection> = Vec::new();
+    while let Some(notification) = cursor.next().await {
+        match notification {
+            Ok(notification) => page.push(notification),
+            Err(err) => {
+                println!("{:?} - collecting notifications", err);
+                return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() }));
+            }
+        }
+    }
+
+    // One extra notification is fetched to know whether there is a next page.
+    let mut next_cursor = None;
+    if page.len() as i64 > limit {
+        page.truncate(limit as usize);
+        if let Some(last) = page.last() {
+            next_cursor = Some(Cursor::after("newest", last.date, &last.id).encode());
+        }
+    }
+
+    let actors: Vec<&String> = page.iter().filter_map(|notification| notification.actor.as_ref()).collect();
+    let usernames = match find_usernames(&client, &actors).await {
+        Ok(usernames) => usernames,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    };
+    let results: Vec<serde_json::Value> = page
+        .iter()
+        .map(|notification| {
+            json!({
+                "id": notification.id,
+                "kind": notification.kind,
+                "actor": notification.actor,
+                "actor_name": notification.actor.as_ref().and_then(|actor| usernames.get(actor)),
+                "skin": notification.skin,
+                "comment": notification.comment,
+                "detail": notification.detail,
+                "date": notification.date,
+                "read": notification.read
+            })
+        })
+        .collect();
+
+    HttpResponse::Ok().json(json!({ "status": 200, "success": true, "notifications": results, "unread": unread, "next_cursor": next_cursor }))
+}
+
+#[post("/read")]
+pub async fn mark_all_read(client: web::Data<Client>, req: HttpRequest) -> HttpResponse {
+    let account = match authenticate(&client, &req).await {
+        Ok(account) => account,
+        Err(response) => return response,
+    };
+    match notifications(&client).update_many(doc! { "recipient": &account.id, "read": false }, doc! { "$set": { "read": true } }, None).await {
+        Ok(result) => HttpResponse::Ok().json(json!({ "status": 200, "success": true, "marked": result.modified_count })),
+        Err(err) => HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    }
+}
+
+#[post("/{id}/read")]
+pub async fn mark_read(client: web::Data<Client>, id: web::Path<String>, req: HttpRequest) -> HttpResponse {
+    let account = match authenticate(&client, &req).await {
+        Ok(account) => account,
+        Err(response) => return response,
+    };
+    match notifications(&client).update_one(doc! { "id": id.into_inner(), "recipient": &account.id }, doc! { "$set": { "read": true } }, None).await {
+        Ok(result) if result.matched_count == 0 => HttpResponse::NotFound().json(json!({ "status": 404, "success": false, "error": "Notification not found" })),
+        Ok(_result) => HttpResponse::Ok().json(json!({ "status": 200, "success": true })),
+        Err(err) => HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    }
+}
+
+fn respond_preferences(account: &Accounts) -> serde_json::Value {
+    let mut preferences = serde_json::Map::new();
+    for kind in NotificationKind::ALL {
+        preferences.insert(kind.name().to_string(), json!(!account.muted_notifications.contains(&kind)));
+    }
+    json!(preferences)
+}
+
+#[get("/preferences")]
+pub async fn get_preferences(client: web::Data<Client>, req: HttpRequest) -> HttpResponse {
+    match session_account(&client, &req).await {
+        Ok(account) => HttpResponse::Ok().json(json!({ "status": 200, "success": true, "preferences": respond_preferences(&account) })),
+        Err(response) => response,
+    }
+}
+
+// Every kind is a form field set to true to receive it or false to mute it, kinds left out keep their setting.
+#[patch("/preferences")]
+pub async fn update_preferences(client: web::Data<Client>, req: HttpRequest, params: web::Form<std::collections::HashMap<String, String>>) -> HttpResponse {
+    let mut account = match authenticate(&client, &req).await {
+        Ok(account) => account,
+        Err(response) => return response,
+    };
+    for (name, value) in params.iter() {
+        let Some(kind) = NotificationKind::ALL.into_iter().find(|kind| kind.name() == name) else {
+            return HttpResponse::BadRequest().json(json!({ "status": 400, "success": false, "error": format!("Unknown notification kind `{}`", name) }));
+        };
+        let enabled = match value.as_str() {
+            "true" => true,
+            "false" => false,
+            _ => return HttpResponse::BadRequest().json(json!({ "status": 400, "success": false, "error": format!("`{}` must be true or false", name) })),
+        };
+        account.muted_notifications.retain(|muted| *muted != kind);
+        if !enabled {
+            account.muted_notifications.push(kind);
+        }
+    }
+
+    let accounts: Collection<Accounts> = client.database("ouja_skins").collection("accounts");
+    let muted: Vec<&str> = account.muted_notifications.iter().map(NotificationKind::name).collect();
+    match accounts.update_one(doc! { "id": &account.id }, doc! { "$set": { "muted_notifications": muted } }, None).await {
+        Ok(_result) => HttpResponse::Ok().json(json!({ "status": 200, "success": true, "preferences": respond_preferences(&account) })),
+        Err(err) => HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    }
+}
@@ -0,0 +1,311 @@
+use std::{fs, path::Path};
+
+use actix_multipart::Multipart;
+use actix_web::{get, post, put, web, HttpRequest, HttpResponse};
+use bson::{doc, Bson, DateTime, Document};
+use mongodb::{Client, Collection};
+use serde_json::json;
+
+use crate::{
+    models::{first_revision, SkinCollection, SkinRevision, TextureKind},
+    texture::phash_bands,
+    util::{authenticate, get_skins_path},
+};
+
+use super::skins::{read_texture_upload, write_texture_files, TextureUpload};
+
+pub fn revision_of(revision: u32, date: DateTime, upload: &TextureUpload) -> SkinRevision {
+    SkinRevision {
+        revision,
+        date,
+        hash: upload.hash.clone(),
+        filename: upload.file_name.clone(),
+        size: upload.buffer.len(),
+        metadata: upload.metadata.clone(),
+        phash: Some(upload.phash.clone()),
+        suspected_copy_of: upload.suspected_copy_of.clone(),
+    }
+}
+
+// Skins uploaded before revisions existed have no history, their current texture is revision 1.
+// The entry of the current revision takes the flag of the skin, a moderator may have cleared it since.
+pub fn revision_history(skin: &SkinCollection) -> Vec<SkinRevision> {
+    if !skin.revisions.is_empty() {
+        let mut history = skin.revisions.clone();
+        for revision in history.iter_mut().filter(|revision| revision.revision == skin.revision) {
+            revision.suspected_copy_of = skin.suspected_copy_of.clone();
+        }
+        return history;
+    }
+    vec![SkinRevision {
+        revision: skin.revision,
+        date: skin.date,
+        hash: skin.hash.clone(),
+        filename: skin.filename.clone(),
+        size: skin.size,
+        metadata: skin.metadata.clone(),
+        phash: skin.phash.clone(),
+        suspected_copy_of: skin.suspected_copy_of.clone(),
+    }]
+}
+
+// Matches the skin only while its current revision is still the one that was read, so two
+// uploads or restores racing each other cannot both move it. Skins stored before revisions
+// existed have no `revision` field and are at the first one.
+fn at_revision(id: &str, revision: u32) -> Document {
+    if revision == first_revision() {
+        doc! { "id": id, "revision": { "$in": [revision, Bson::Null] } }
+    } else {
+        doc! { "id": id, "revision": revision }
+    }
+}
+
+fn revision_path(id: &str, revision: u32, legacy: bool) -> String {
+    if legacy {
+        format!("{}/{}.r{}.legacy.png", get_skins_path(), id, revision)
+    } else {
+        format!("{}/{}.r{}.png", get_skins_path(), id, revision)
+    }
+}
+
+// The current texture is always `{id}.png`, older revisions are kept as `{id}.r{revision}.png`.
+// Before the current texture is replaced it gets copied to its revision file.
+fn archive_current(id: &str, revision: u32) -> std::io::Result<()> {
+    let skins_path = get_skins_path();
+    let archived = revision_path(id, revision, false);
+    if !Path::new(&archived).exists() {
+        fs::copy(format!("{}/{}.png", skins_path, id), archived)?;
+    }
+    let legacy = format!("{}/{}.legacy.png", skins_path, id);
+    let archived_legacy = revision_path(id, revision, true);
+    if Path::new(&legacy).exists() && !Path::new(&archived_legacy).exists() {
+        fs::copy(legacy, archived_legacy)?;
+    }
+    Ok(())
+}
+
+fn activate_revision(id: &str, revision: u32) -> std::io::Result<()> {
+    let skins_path = get_skins_path();
+    fs::copy(revision_path(id, revision, false), format!("{}/{}.png", skins_path, id))?;
+    let legacy = format!("{}/{}.legacy.png", skins_path, id);
+    let archived_legacy = revision_path(id, revision, true);
+    if Path::new(&archived_legacy).exists() {
+        fs::copy(archived_legacy, legacy)?;
+    } else if Path::new(&legacy).exists() {
+        fs::remove_file(legacy)?;
+    }
+    Ok(())
+}
+
+pub fn remove_revision_files(id: &str, revisions: &[SkinRevision]) {
+    for revision in revisions {
+        for path in [revision_path(id, revision.revision, false), revision_path(id, revision.revision, true)] {
+            if let Err(err) = fs::remove_file(&path) {
+                if err.kind() != std::io::ErrorKind::NotFound {
+                    println!("{} - removing {}", err, path);
+                }
+            }
+        }
+    }
+}
+
+// The fields of the skin document that mirror its current revision.
+fn current_fields(revision: &SkinRevision, history: &[SkinRevision]) -> bson::ser::Result<Document> {
+    let mut fields = doc! {
+        "hash": &revision.hash,
+        "filename": &revision.filename,
+        "size": revision.size as i64,
+        "metadata": bson::to_bson(&revision.metadata)?,
+        "phash": &revision.phash,
+        "phash_bands": revision.phash.as_deref().map(phash_bands).unwrap_or_default(),
+        "suspected_copy_of": &revision.suspected_copy_of,
+        "revision": revision.revision,
+    };
+    fields.insert("revisions", bson::to_bson(history)?);
+    Ok(fields)
+}
+
+async fn find_owned_skin(client: &Client, id: &str, req: &HttpRequest) -> Result<SkinCollection, HttpResponse> {
+    let account = authenticate(client, req).await?;
+    let skins: Collection<SkinCollection> = client.database("ouja_skins").collection("skins");
+    match skins.find_one(doc! { "id": id, "kind": TextureKind::Skin.filter() }, None).await {
+        Ok(Some(skin)) if skin.owner == account.id => Ok(skin),
+        Ok(Some(_skin)) => Err(HttpResponse::Forbidden().json(json!({ "status": 403, "success": false, "error": "You do not own this skin." }))),
+        Ok(None) => Err(HttpResponse::NotFound().json(json!({ "status": 404, "success": false, "error": "Skin not found" }))),
+        Err(err) => Err(HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() }))),
+    }
+}
+
+#[put("/{id}/revisions")]
+pub async fn upload_revision(client: web::Data<Client>, id: web::Path<String>, mut payload: Multipart, req: HttpRequest) -> HttpResponse {
+    let id = id.into_inner();
+    let skin = match find_owned_skin(&client, &id, &req).await {
+        Ok(skin) => skin,
+        Err(response) => return response,
+    };
+    let upload = match read_texture_upload(&client, &mut payload, &skin.owner, TextureKind::Skin).await {
+        Ok(upload) => upload,
+        Err(response) => return response,
+    };
+
+    let mut history = revision_history(&skin);
+    if let Some(existing) = history.iter().find(|revision| revision.hash == upload.hash) {
+        return HttpResponse::Forbidden().json(json!({ "status": 403, "success": false, "error": format!("This texture is already revision {}, restore it instead!", existing.revision) }));
+    }
+    let skins: Collection<SkinCollection> = client.database("ouja_skins").collection("skins");
+    match skins.find_one(doc! { "hash": &upload.hash, "id": { "$ne": &id } }, None).await {
+        Ok(Some(_skin)) => return HttpResponse::Forbidden().json(json!({ "status": 403, "success": false, "error": "Skin file already exists!" })),
+        Ok(None) => {},
+        Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    }
+
+    let number = history.iter().map(|revision| revision.revision).max().unwrap_or(0) + 1;
+    let revision = revision_of(number, DateTime::now(), &upload);
+    history.push(revision.clone());
+
+    if let Err(err) = archive_current(&id, skin.revision) {
+        println!("{} - archiving revision", err);
+        return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() }));
+    }
+    let fields = match current_fields(&revision, &history) {
+        Ok(fields) => fields,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    };
+    // The revision is claimed before the texture is written, the upload that lost a race leaves the files alone.
+    match skins.update_one(at_revision(&id, skin.revision), doc! { "$set": fields }, None).await {
+        Ok(result) if result.matched_count == 0 => return revision_conflict(),
+        Ok(_result) => {},
+        Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    }
+
+    if let Err(err) = write_texture_files(&id, &upload) {
+        println!("{} - storing revision", err);
+        rollback(&client, &id, number, &skin).await;
+        return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() }));
+    }
+    HttpResponse::Ok().json(json!({
+        "status": 200,
+        "success": true,
+        "revision": number,
+        "suspected_copy_of": &upload.suspected_copy_of,
+        "listed": upload.suspected_copy_of.is_empty()
+    }))
+}
+
+fn revision_conflict() -> HttpResponse {
+    HttpResponse::Conflict().json(json!({ "status": 409, "success": false, "error": "The skin was changed in the meantime, reload it and try again." }))
+}
+
+// Points the skin back at the revision it was at when its texture files could not be written.
+async fn rollback(client: &Client, id: &str, claimed: u32, skin: &SkinCollection) {
+    let history = revision_history(skin);
+    let Some(previous) = history.iter().find(|revision| revision.revision == skin.revision) else {
+        return;
+    };
+    let fields = match current_fields(previous, &history) {
+        Ok(fields) => fields,
+        Err(err) => {
+            println!("{} - rolling back skin {}", err, id);
+            return;
+        }
+    };
+    let skins: Collection<SkinCollection> = client.database("ouja_skins").collection("skins");
+    if let Err(err) = skins.update_one(doc! { "id": id, "revision": claimed }, doc! { "$set": fields }, None).await {
+        println!("{:?} - rolling back skin {}", err, id);
+    }
+}
+
+#[get("/{id}/revisions")]
+pub async fn get_revisions(client: web::Data<Client>, id: web::Path<String>) -> HttpResponse {
+    let id = id.into_inner();
+    let skins: Collection<SkinCollection> = client.database("ouja_skins").collection("skins");
+    match skins.find_one(doc! { "id": &id, "kind": TextureKind::Skin.filter() }, None).await {
+        Ok(Some(skin)) => {
+            let revisions: Vec<serde_json::Value> = revision_history(&skin)
+                .into_iter()
+                .rev()
+                .map(|revision| json!({
+                    "revision": revision.revision,
+                    "date": revision.date,
+                    "hash": revision.hash,
+                    "size": revision.size,
+                    "metadata": revision.metadata,
+                    "current": revision.revision == skin.revision,
+                }))
+                .collect();
+            HttpResponse::Ok().json(json!({ "status": 200, "success": true, "current": skin.revision, "revisions": revisions }))
+        },
+        Ok(None) => HttpResponse::NotFound().json(json!({ "status": 404, "success": false, "error": "Skin not found" })),
+        Err(err) => HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    }
+}
+
+#[get("/{id}/revisions/{revision}.png")]
+pub async fn get_revision_texture(client: web::Data<Client>, path: web::Path<(String, u32)>) -> HttpResponse {
+    let (id, number) = path.into_inner();
+    let skins: Collection<SkinCollection> = client.database("ouja_skins").collection("skins");
+    match skins.find_one(doc! { "id": &id, "kind": TextureKind::Skin.filter() }, None).await {
+        Ok(Some(skin)) => {
+            if !revision_history(&skin).iter().any(|revision| revision.revision == number) {
+                return HttpResponse::NotFound().json(json!({ "status": 404, "success": false, "error": "Revision not found" }));
+            }
+            let path = if number == skin.revision {
+                format!("{}/{}.png", get_skins_path(), id)
+            } else {
+                revision_path(&id, number, false)
+            };
+            match web::block(move || fs::read(path)).await {
+                Ok(Ok(buffer)) => HttpResponse::Ok().content_type("image/png").body(buffer),
+                Ok(Err(err)) => HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+                Err(err) => HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+            }
+        },
+        Ok(None) => HttpResponse::NotFound().json(json!({ "status": 404, "success": false, "error": "Skin not found" })),
+        Err(err) => HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    }
+}
+
+// Rolling back only moves the current pointer, later revisions stay in the history.
+#[post("/{id}/revisions/{revision}/restore")]
+pub async fn restore_revision(client: web::Data<Client>, path: web::Path<(String, u32)>, req: HttpRequest) -> HttpResponse {
+    let (id, number) = path.into_inner();
+    let skin = match find_owned_skin(&client, &id, &req).await {
+        Ok(skin) => skin,
+        Err(response) => return response,
+    };
+    if number == skin.revision {
+        return HttpResponse::BadRequest().json(json!({ "status": 400, "success": false, "error": "This is already the current revision!" }));
+    }
+    let history = revision_history(&skin);
+    let Some(revision) = history.iter().find(|revision| revision.revision == number) else {
+        return HttpResponse::NotFound().json(json!({ "status": 404, "success": false, "error": "Revision not found" }));
+    };
+
+    if let Err(err) = archive_current(&id, skin.revision) {
+        println!("{} - archiving revision", err);
+        return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() }));
+    }
+    let fields = match current_fields(revision, &history) {
+        Ok(fields) => fields,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    };
+    let skins: Collection<SkinCollection> = client.database("ouja_skins").collection("skins");
+    match skins.update_one(at_revision(&id, skin.revision), doc! { "$set": fields }, None).await {
+        Ok(result) if result.matched_count == 0 => return revision_conflict(),
+        Ok(_result) => {},
+        Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    }
+
+    if let Err(err) = activate_revision(&id, number) {
+        println!("{} - restoring revision", err);
+        rollback(&client, &id, number, &skin).await;
+        return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() }));
+    }
+    HttpResponse::Ok().json(json!({
+        "status": 200,
+        "success": true,
+        "revision": number,
+        "suspected_copy_of": &revision.suspected_copy_of,
+        "listed": revision.suspected_copy_of.is_empty()
+    }))
+}
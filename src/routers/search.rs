@@ -0,0 +1,190 @@
+use actix_web::{get, web, HttpResponse};
+use bson::{doc, Document};
+use chrono::DateTime as ChronoDateTime;
+use futures_util::stream::StreamExt;
+use mongodb::{bson::DateTime, options::FindOptions, Client, Collection};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{
+    cursor::Cursor,
+    models::{Accounts, SkinModel, TextureKind},
+    tags::{normalize_tag, parse_category},
+    util::escape_regex,
+};
+
+use super::skins::{visible_filter, RespondSkin};
+
+#[derive(Serialize, Deserialize)]
+pub struct SearchParams {
+    q: Option<String>,
+    owner: Option<String>,
+    model: Option<String>,
+    resolution: Option<usize>,
+    tag: Option<String>,
+    category: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+    sort: Option<String>,
+    cursor: Option<String>,
+    limit: Option<i64>,
+}
+
+fn parse_date(date: &str) -> Option<DateTime> {
+    ChronoDateTime::parse_from_rfc3339(date)
+        .ok()
+        .map(|date| DateTime::from_millis(date.timestamp_millis()))
+}
+
+fn bad_request(error: &str) -> HttpResponse {
+    HttpResponse::BadRequest().json(json!({ "status": 400, "success": false, "error": error }))
+}
+
+#[get("")]
+pub async fn search_skins(client: web::Data<Client>, params: web::Query<SearchParams>) -> HttpResponse {
+    let params = params.into_inner();
+    let limit = params.limit.unwrap_or(24).clamp(1, 100);
+    let query = params.q.as_deref().map(str::trim).filter(|q| !q.is_empty());
+
+    // Relevance only means something with a search query, without one it falls back to newest.
+    let sort = match (params.sort.as_deref(), query) {
+        (None, Some(_)) | (Some("relevance"), Some(_)) => "relevance",
+        (None, None) | (Some("relevance"), None) | (Some("newest"), _) => "newest",
+        (Some("popular"), _) => "popular",
+        (Some("trending"), _) => "trending",
+        (Some(_), _) => return bad_request("Sort must be newest, popular, trending or relevance!"),
+    };
+
+    // Skins held back as suspected copies stay out of search like they do out of feeds.
+    let mut filter = visible_filter(TextureKind::Skin);
+    if let Some(query) = query {
+        filter.insert("$text", doc! { "$search": query });
+    }
+
+    if let Some(owner) = &params.owner {
+        let accounts: Collection<Accounts> = client.database("ouja_skins").collection("accounts");
+        match accounts.find_one(doc! { "username": { "$regex": format!("^{}$", escape_regex(owner)), "$options": "i" } }, None).await {
+            Ok(Some(account)) => {
+                filter.insert("owner", account.id);
+            },
+            Ok(None) => return HttpResponse::NotFound().json(json!({ "status": 404, "success": false, "error": "User not found" })),
+            Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+        }
+    }
+
+    // Skins from before model detection have no model and are classic.
+    match params.model.as_deref().map(SkinModel::parse) {
+        None => {},
+        Some(Some(SkinModel::Classic)) => {
+            filter.insert("metadata.Image.model", doc! { "$ne": "slim" });
+        },
+        Some(Some(SkinModel::Slim)) => {
+            filter.insert("metadata.Image.model", "slim");
+        },
+        Some(None) => return bad_request("Model must be classic or slim!"),
+    }
+
+    if let Some(resolution) = params.resolution {
+        filter.insert("metadata.Image.width", resolution as i64);
+    }
+
+    if let Some(tag) = &params.tag {
+        match normalize_tag(tag) {
+            Some(tag) => {
+                filter.insert("tags", tag);
+            },
+            None => return bad_request("Invalid tag!"),
+        }
+    }
+
+    if let Some(category) = &params.category {
+        match parse_category(category) {
+            Ok(Some(category)) => {
+                filter.insert("category", category);
+            },
+            Ok(None) => {},
+            Err(error) => return bad_request(&error),
+        }
+    }
+
+    let mut date_range = Document::new();
+    if let Some(from) = &params.from {
+        match parse_date(from) {
+            Some(from) => date_range.insert("$gte", from),
+            None => return bad_request("`from` must be an RFC 3339 date!"),
+        };
+    }
+    if let Some(to) = &params.to {
+        match parse_date(to) {
+            Some(to) => date_range.insert("$lte", to),
+            None => return bad_request("`to` must be an RFC 3339 date!"),
+        };
+    }
+    if !date_range.is_empty() {
+        filter.insert("date", date_range);
+    }
+
+    let cursor = match &params.cursor {
+        Some(cursor) => match Cursor::decode(cursor) {
+            Some(cursor) if cursor.sort() == sort => Some(cursor),
+            _ => return bad_request("Invalid cursor!"),
+        },
+        None => None,
+    };
+
+    let mut offset: u64 = 0;
+    match cursor {
+        Some(Cursor::After { date, id, .. }) => {
+            let date = DateTime::from_millis(date);
+            filter.insert("$or", vec![
+                doc! { "date": { "$lt": date } },
+                doc! { "date": date, "id": { "$lt": id } },
+            ]);
+        },
+        Some(Cursor::Offset { offset: skip, .. }) => offset = skip,
+        None => {},
+    }
+
+    let order = match sort {
+        "relevance" => doc! { "score": { "$meta": "textScore" }, "date": -1, "id": -1 },
+        "popular" => doc! { "likes": -1, "date": -1, "id": -1 },
+        "trending" => doc! { "trending": -1, "date": -1, "id": -1 },
+        _ => doc! { "date": -1, "id": -1 },
+    };
+    let mut options = FindOptions::builder().sort(order).skip(offset).limit(limit + 1).build();
+    if sort == "relevance" {
+        options.projection = Some(doc! { "score": { "$meta": "textScore" } });
+    }
+
+    let collection: Collection<RespondSkin> = client.database("ouja_skins").collection("skins");
+    match collection.find(filter, options).await {
+        Ok(mut results) => {
+            let mut skins: Vec<RespondSkin> = Vec::new();
+            while let Some(skin) = results.next().await {
+                match skin {
+                    Ok(skin) => skins.push(skin),
+                    Err(err) => {
+                        println!("{:?} - collecting skins", err);
+                        return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() }));
+                    }
+                }
+            }
+
+            // One extra skin is fetched to know whether there is a next page.
+            let next_cursor = if skins.len() as i64 > limit {
+                skins.truncate(limit as usize);
+                let last = skins.last().unwrap();
+                let cursor = match sort {
+                    "newest" => Cursor::after(sort, last.date, &last.id),
+                    _ => Cursor::Offset { sort: sort.to_string(), offset: offset.saturating_add(limit as u64) },
+                };
+                Some(cursor.encode())
+            } else {
+                None
+            };
+
+            HttpResponse::Ok().json(json!({ "status": 200, "success": true, "skins": skins, "next_cursor": next_cursor }))
+        },
+        Err(err) => HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    }
+}
@@ -1,18 +1,36 @@
-use std::{fs, io::Write, str::from_utf8};
+use std::{collections::HashMap, fs, io::Write};
 
 use actix_multipart::{Multipart};
-use actix_web::{put, web, HttpRequest, HttpResponse, get};
-use bson::doc;
+use actix_web::{delete, patch, put, web, HttpRequest, HttpResponse, get};
+use bson::{doc, Document};
 use futures_util::stream::StreamExt as _;
-use mongodb::{bson::DateTime, Client, Collection};
+use image::ImageFormat;
+use mongodb::{bson::DateTime, options::FindOptions, Client, Collection};
 use serde::{Serialize, Deserialize};
 use serde_json::json;
 use uuid::Uuid;
 
+use super::{
+    collections::remove_skin_from_collections,
+    comments::remove_skin_comments,
+    likes::remove_skin_likes,
+    revisions::{remove_revision_files, revision_history, revision_of},
+};
+
 use crate::{
-    magic_crypt::encrypt,
-    models::{Accounts, SkinMeta, SkinCollection},
-    util::{get_session_token, get_skins_path, verified_csrf},
+    models::{first_revision, Accounts, SkinMeta, SkinCollection, SkinModel, SkinRevision, StatEvent, TextureKind},
+    render::{parse_hex_color, render_isometric, RenderOptions},
+    stats::{remove_skin_stats, spawn_record_hit},
+    tags::{parse_category, parse_tags},
+    texture::{
+        apply_jpeg_alpha, convert_legacy_skin, decode_image, describe_cape_resolutions, describe_resolutions,
+        detect_model, encode_png, find_cape_resolution, find_resolution, has_visible_pixels, is_legacy, perceptual_hash,
+        phash_bands, phash_distance, texture_hash,
+    },
+    util::{
+        authenticate, blocks_near_duplicates, get_cape_resolutions, get_phash_threshold, get_session_token, get_skin_resolutions,
+        escape_regex, get_skins_path, verified_csrf,
+    },
 };
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -21,19 +39,249 @@ pub struct RespondSkin {
     pub date: DateTime,
     pub title: String,
     pub description: String,
-    pub owner: String
+    pub owner: String,
+    pub metadata: SkinMeta,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub category: Option<String>,
+    #[serde(default = "first_revision")]
+    pub revision: u32,
+    #[serde(default, skip_serializing)]
+    pub revisions: Vec<SkinRevision>,
+    #[serde(default)]
+    pub likes: i64,
+    #[serde(default)]
+    pub views: i64,
+    #[serde(default)]
+    pub downloads: i64,
 }
 
 #[get("/{id}.json")]
-pub async fn get_skin(client: web::Data<Client>, id: web::Path<String>) -> HttpResponse {
+pub async fn get_skin(client: web::Data<Client>, id: web::Path<String>, req: HttpRequest) -> HttpResponse {
     let id = id.into_inner();
+    let response = find_texture(client.clone(), id.clone(), TextureKind::Skin).await;
+    if response.status().is_success() {
+        spawn_record_hit(client.get_ref().clone(), id, StatEvent::View, &req);
+    }
+    response
+}
+
+#[get("/{id}.png")]
+pub async fn get_skin_texture(client: web::Data<Client>, id: web::Path<String>, req: HttpRequest) -> HttpResponse {
+    let id = id.into_inner();
+    let response = serve_texture(client.clone(), id.clone(), TextureKind::Skin).await;
+    if response.status().is_success() {
+        spawn_record_hit(client.get_ref().clone(), id, StatEvent::Download, &req);
+    }
+    response
+}
+
+pub async fn find_texture(client: web::Data<Client>, id: String, kind: TextureKind) -> HttpResponse {
+    let collection: Collection<RespondSkin> = client.database("ouja_skins").collection("skins");
+    match collection.find_one(doc! { "id": id, "kind": kind.filter() }, None).await {
+        Ok(None) => {
+            HttpResponse::NotFound().json(json!({ "status": 404, "success": false, "error": format!("{} not found", kind.title()) }))
+        },
+        Ok(Some(skin)) => {
+            let mut response = json!(skin);
+            response["revision_count"] = json!(skin.revisions.len().max(1));
+            HttpResponse::Ok().json(response)
+        },
+        Err(err) => {
+            HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() }))
+        }
+    }
+}
+
+pub async fn serve_texture(client: web::Data<Client>, id: String, kind: TextureKind) -> HttpResponse {
     let collection: Collection<RespondSkin> = client.database("ouja_skins").collection("skins");
-    match collection.find_one(doc! { "id": id }, None).await {
+    match collection.find_one(doc! { "id": &id, "kind": kind.filter() }, None).await {
+        Ok(None) => {
+            HttpResponse::NotFound().json(json!({ "status": 404, "success": false, "error": format!("{} not found", kind.title()) }))
+        },
+        Ok(Some(skin)) => {
+            match web::block(move || fs::read(format!("{}/{}.png", get_skins_path(), skin.id))).await {
+                Ok(Ok(buffer)) => HttpResponse::Ok().content_type("image/png").body(buffer),
+                Ok(Err(err)) => {
+                    println!("{} - reading texture", err);
+                    HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() }))
+                },
+                Err(err) => HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+            }
+        },
+        Err(err) => {
+            HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() }))
+        }
+    }
+}
+
+// Textures that are listed to other people, in feeds and collections. Near duplicates of
+// someone else's work stay hidden there until a moderator has cleared them.
+pub fn visible_filter(kind: TextureKind) -> Document {
+    doc! { "kind": kind.filter(), "suspected_copy_of.0": { "$exists": false } }
+}
+
+// Returns the ids of textures whose perceptual hash is within the configured threshold,
+// ignoring the ones that belong to `exclude_owner`.
+pub async fn find_similar_textures(
+    client: &Client,
+    phash: &str,
+    kind: TextureKind,
+    exclude_owner: Option<&str>,
+) -> mongodb::error::Result<Vec<(String, u32)>> {
+    let collection: Collection<Document> = client.database("ouja_skins").collection("skins");
+    let mut filter = doc! { "kind": kind.filter(), "phash_bands": { "$in": phash_bands(phash) } };
+    if let Some(owner) = exclude_owner {
+        filter.insert("owner", doc! { "$ne": owner });
+    }
+    let options = FindOptions::builder().projection(doc! { "id": 1, "phash": 1 }).build();
+    let mut candidates = collection.find(filter, options).await?;
+
+    let threshold = get_phash_threshold();
+    let mut similar: Vec<(String, u32)> = Vec::new();
+    while let Some(candidate) = candidates.next().await {
+        let candidate = candidate?;
+        let (Ok(id), Ok(other)) = (candidate.get_str("id"), candidate.get_str("phash")) else {
+            continue;
+        };
+        if let Some(distance) = phash_distance(phash, other) {
+            if distance <= threshold {
+                similar.push((id.to_string(), distance));
+            }
+        }
+    }
+    similar.sort_by_key(|(_id, distance)| *distance);
+    Ok(similar)
+}
+
+#[get("/{id}/copies")]
+pub async fn get_suspected_copies(client: web::Data<Client>, id: web::Path<String>, req: HttpRequest) -> HttpResponse {
+    let id = id.into_inner();
+    if let Some(token) = get_session_token(&req) {
+        let accounts: Collection<Accounts> = client.database("ouja_skins").collection("accounts");
+        let skins: Collection<SkinCollection> = client.database("ouja_skins").collection("skins");
+        match accounts.find_one(doc! { "session": token }, None).await {
+            Ok(Some(account)) if account.moderator => {
+                match skins.find_one(doc! { "id": &id }, None).await {
+                    Ok(Some(skin)) => {
+                        let Some(phash) = &skin.phash else {
+                            return HttpResponse::Ok().json(json!({ "status": 200, "success": true, "copies": [] }));
+                        };
+                        let similar = match find_similar_textures(&client, phash, skin.kind, Some(&skin.owner)).await {
+                            Ok(similar) => similar,
+                            Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+                        };
+                        let ids: Vec<&String> = similar.iter().map(|(id, _distance)| id).collect();
+                        let mut copies = match skins.find(doc! { "id": { "$in": ids } }, None).await {
+                            Ok(copies) => copies,
+                            Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+                        };
+
+                        let mut results: Vec<serde_json::Value> = Vec::new();
+                        while let Some(copy) = copies.next().await {
+                            match copy {
+                                Ok(copy) => {
+                                    let distance = similar.iter().find(|(id, _distance)| id == &copy.id).map(|(_id, distance)| *distance);
+                                    results.push(json!({
+                                        "id": copy.id,
+                                        "title": copy.title,
+                                        "owner": copy.owner,
+                                        "date": copy.date,
+                                        "distance": distance,
+                                        // Uploaded after the original, so the likely copy rather than the source.
+                                        "newer": copy.date > skin.date,
+                                    }));
+                                },
+                                Err(err) => {
+                                    println!("{:?} - collecting copies", err);
+                                    return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() }));
+                                }
+                            }
+                        }
+                        results.sort_by_key(|copy| copy["distance"].as_u64());
+
+                        HttpResponse::Ok().json(json!({ "status": 200, "success": true, "copies": results }))
+                    },
+                    Ok(None) => HttpResponse::NotFound().json(json!({ "status": 404, "success": false, "error": "Skin not found" })),
+                    Err(err) => HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+                }
+            },
+            Ok(Some(_account)) => HttpResponse::Forbidden().json(json!({ "status": 403, "success": false, "error": "Only moderators can do this." })),
+            Ok(None) => HttpResponse::Unauthorized().json(json!({ "status": 401, "success": false, "error": "Could not authenticate request." })),
+            Err(err) => HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+        }
+    } else {
+        HttpResponse::Unauthorized().json(json!({ "status": 401, "success": false, "error": "Could not authenticate request." }))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Render3dParams {
+    yaw: Option<f32>,
+    pitch: Option<f32>,
+    light: Option<f32>,
+    ambient: Option<f32>,
+    scale: Option<u32>,
+    background: Option<String>,
+}
+
+#[get("/{id}/render3d")]
+pub async fn render_skin_3d(client: web::Data<Client>, id: web::Path<String>, params: web::Query<Render3dParams>) -> HttpResponse {
+    let id = id.into_inner();
+    let mut options = RenderOptions::default();
+    if let Some(yaw) = params.yaw {
+        options.yaw = yaw;
+    }
+    if let Some(pitch) = params.pitch {
+        if !(-90.0..=90.0).contains(&pitch) {
+            return HttpResponse::BadRequest().json(json!({ "status": 400, "success": false, "error": "Pitch must be between -90 and 90!" }));
+        }
+        options.pitch = pitch;
+    }
+    if let Some(light) = params.light {
+        options.light = light.clamp(0.0, 1.0);
+    }
+    if let Some(ambient) = params.ambient {
+        options.ambient = ambient.clamp(0.0, 1.0);
+    }
+    if let Some(scale) = params.scale {
+        if !(1..=32).contains(&scale) {
+            return HttpResponse::BadRequest().json(json!({ "status": 400, "success": false, "error": "Scale must be between 1 and 32!" }));
+        }
+        options.scale = scale;
+    }
+    if let Some(background) = &params.background {
+        match parse_hex_color(background) {
+            Some(color) => options.background = Some(color),
+            None => return HttpResponse::BadRequest().json(json!({ "status": 400, "success": false, "error": "Background must be a hex color!" })),
+        }
+    }
+
+    let collection: Collection<RespondSkin> = client.database("ouja_skins").collection("skins");
+    match collection.find_one(doc! { "id": &id, "kind": TextureKind::Skin.filter() }, None).await {
         Ok(None) => {
             HttpResponse::NotFound().json(json!({ "status": 404, "success": false, "error": "Skin not found" }))
         },
         Ok(Some(skin)) => {
-            HttpResponse::Ok().json(json!(skin))
+            let path = format!("{}/{}.png", get_skins_path(), skin.id);
+            let model = match skin.metadata {
+                SkinMeta::Image { model, .. } => model,
+                SkinMeta::Cape { .. } => SkinModel::Classic,
+            };
+            let rendered = web::block(move || {
+                let buffer = fs::read(path).map_err(|err| err.to_string())?;
+                let texture = image::load_from_memory(&buffer).map_err(|err| err.to_string())?.to_rgba8();
+                encode_png(render_isometric(&texture, model, &options)).map_err(|err| err.to_string())
+            }).await;
+
+            match rendered {
+                Ok(Ok(png)) => HttpResponse::Ok().content_type("image/png").body(png),
+                Ok(Err(err)) => {
+                    println!("{} - rendering skin", err);
+                    HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err }))
+                },
+                Err(err) => HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+            }
         },
         Err(err) => {
             HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() }))
@@ -41,11 +289,382 @@ pub async fn get_skin(client: web::Data<Client>, id: web::Path<String>) -> HttpR
     }
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct UpdateSkinParams {
+    title: Option<String>,
+    description: Option<String>,
+    tags: Option<String>,
+    category: Option<String>,
+}
+
+// The same length rules apply to uploads and edits.
+pub fn check_details(title: &str, description: &str) -> Option<HttpResponse> {
+    if title.len() > 16 {
+        return Some(HttpResponse::Forbidden().json(json!({ "status": 403, "success": false, "error": "Title cannot be larger than 16 characters!" })));
+    }
+    if let Err(error) = check_text("Description", description) {
+        return Some(HttpResponse::Forbidden().json(json!({ "status": 403, "success": false, "error": error })));
+    }
+    None
+}
+
+// Free text written by users, descriptions and comments alike.
+pub fn check_text(label: &str, text: &str) -> Result<(), String> {
+    if text.len() > 256 {
+        return Err(format!("{} cannot be larger than 256 characters!", label));
+    }
+    Ok(())
+}
+
+pub fn check_tags(tags: &str, category: &str) -> Result<(Vec<String>, Option<String>), String> {
+    Ok((parse_tags(tags)?, parse_category(category)?))
+}
+
+// Titles are unique per owner, ignoring case.
+pub fn title_filter(title: &str) -> Document {
+    doc! { "$regex": format!("^{}$", escape_regex(title)), "$options": "i" }
+}
+
+#[patch("/{id}")]
+pub async fn update_skin(client: web::Data<Client>, id: web::Path<String>, req: HttpRequest, params: web::Form<UpdateSkinParams>) -> HttpResponse {
+    update_texture(client, id.into_inner(), req, params.into_inner(), TextureKind::Skin).await
+}
+
+#[delete("/{id}")]
+pub async fn delete_skin(client: web::Data<Client>, id: web::Path<String>, req: HttpRequest) -> HttpResponse {
+    delete_texture(client, id.into_inner(), req, TextureKind::Skin).await
+}
+
+pub async fn update_texture(client: web::Data<Client>, id: String, req: HttpRequest, params: UpdateSkinParams, kind: TextureKind) -> HttpResponse {
+    let account = match authenticate(&client, &req).await {
+        Ok(account) => account,
+        Err(response) => return response,
+    };
+    let skins: Collection<SkinCollection> = client.database("ouja_skins").collection("skins");
+    let skin = match skins.find_one(doc! { "id": &id, "kind": kind.filter() }, None).await {
+        Ok(Some(skin)) => skin,
+        Ok(None) => return HttpResponse::NotFound().json(json!({ "status": 404, "success": false, "error": format!("{} not found", kind.title()) })),
+        Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    };
+    if skin.owner != account.id {
+        return HttpResponse::Forbidden().json(json!({ "status": 403, "success": false, "error": format!("You do not own this {}.", kind.name()) }));
+    }
+
+    let title = params.title.unwrap_or(skin.title);
+    let description = params.description.unwrap_or(skin.description);
+    if let Some(response) = check_details(&title, &description) {
+        return response;
+    }
+    let (tags, category) = match check_tags(
+        &params.tags.unwrap_or_else(|| skin.tags.join(",")),
+        &params.category.unwrap_or_else(|| skin.category.unwrap_or_default()),
+    ) {
+        Ok(parsed) => parsed,
+        Err(error) => return HttpResponse::BadRequest().json(json!({ "status": 400, "success": false, "error": error })),
+    };
+
+    match skins.find_one(doc! { "owner": &account.id, "kind": kind.filter(), "id": { "$ne": &id }, "title": title_filter(&title) }, None).await {
+        Ok(Some(_skin)) => HttpResponse::Forbidden().json(json!({ "status": 401, "success": false, "error": "Title already exists!" })),
+        Ok(None) => {
+            match skins.update_one(doc! { "id": &id }, doc! { "$set": { "title": &title, "description": &description, "tags": &tags, "category": &category } }, None).await {
+                Ok(_update_result) => {
+                    HttpResponse::Ok().json(json!({ "status": 200, "success": true, kind.name(): { "id": &id, "title": &title, "description": &description, "tags": &tags, "category": &category } }))
+                },
+                Err(err) => HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+            }
+        },
+        Err(err) => HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    }
+}
+
+pub async fn delete_texture(client: web::Data<Client>, id: String, req: HttpRequest, kind: TextureKind) -> HttpResponse {
+    let account = match authenticate(&client, &req).await {
+        Ok(account) => account,
+        Err(response) => return response,
+    };
+    let skins: Collection<SkinCollection> = client.database("ouja_skins").collection("skins");
+    let skin = match skins.find_one(doc! { "id": &id, "kind": kind.filter() }, None).await {
+        Ok(Some(skin)) => skin,
+        Ok(None) => return HttpResponse::NotFound().json(json!({ "status": 404, "success": false, "error": format!("{} not found", kind.title()) })),
+        Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    };
+    if skin.owner != account.id {
+        return HttpResponse::Forbidden().json(json!({ "status": 403, "success": false, "error": format!("You do not own this {}.", kind.name()) }));
+    }
+
+    if let Err(err) = remove_texture(&client, &skin).await {
+        return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() }));
+    }
+
+    HttpResponse::Ok().json(json!({ "status": 200, "success": true }))
+}
+
+// Removes a texture with everything that refers to it. Only failing to remove the texture
+// itself is an error, the rest is cleaned up as well as it goes and logged.
+pub async fn remove_texture(client: &Client, skin: &SkinCollection) -> mongodb::error::Result<()> {
+    let accounts: Collection<Accounts> = client.database("ouja_skins").collection("accounts");
+    let skins: Collection<SkinCollection> = client.database("ouja_skins").collection("skins");
+    skins.delete_one(doc! { "id": &skin.id }, None).await?;
+    if let Err(err) = accounts.update_one(doc! { "id": &skin.owner }, doc! { "$pull": { skin.kind.account_field(): &skin.id } }, None).await {
+        println!("{:?} - Updating user", err);
+    }
+    if let Err(err) = remove_skin_likes(client, &skin.id).await {
+        println!("{:?} - Removing likes", err);
+    }
+    if let Err(err) = remove_skin_stats(client, &skin.id).await {
+        println!("{:?} - Removing stats", err);
+    }
+    if let Err(err) = remove_skin_comments(client, &skin.id).await {
+        println!("{:?} - Removing comments", err);
+    }
+    if let Err(err) = remove_skin_from_collections(client, &skin.id).await {
+        println!("{:?} - Removing from collections", err);
+    }
+    if let Err(err) = accounts.update_many(doc! { skin.kind.active_field(): &skin.id }, doc! { "$set": { skin.kind.active_field(): null } }, None).await {
+        println!("{:?} - Clearing active {}", err, skin.kind.name());
+    }
+
+    // The document is gone at this point, a file that fails to delete is only logged.
+    remove_revision_files(&skin.id, &revision_history(skin));
+    let skins_path = get_skins_path();
+    for path in [format!("{}/{}.png", skins_path, skin.id), format!("{}/{}.legacy.png", skins_path, skin.id)] {
+        if let Err(err) = fs::remove_file(&path) {
+            if err.kind() != std::io::ErrorKind::NotFound {
+                println!("{} - removing {}", err, path);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Largest text field of a multipart form, the longest text the API stores is far below it.
+const MAX_MULTIPART_FIELD: usize = 64 * 1000;
+
+// The text fields of a multipart form and the content of its one file field.
+pub struct MultipartForm {
+    pub fields: HashMap<String, String>,
+    pub buffer: Vec<u8>,
+    pub file_name: Option<String>,
+}
+
+// Reads a multipart form, stopping as soon as the file gets larger than `max_file_size`
+// or a text field larger than `MAX_MULTIPART_FIELD`, so nothing bigger is ever buffered.
+pub async fn read_multipart(payload: &mut Multipart, file_field: &str, max_file_size: usize) -> Result<MultipartForm, HttpResponse> {
+    let mut form = MultipartForm { fields: HashMap::new(), buffer: Vec::new(), file_name: None };
+    while let Some(Ok(mut field)) = payload.next().await {
+        let name = field.name().to_string();
+        let limit = if name == file_field { max_file_size } else { MAX_MULTIPART_FIELD };
+        let mut data: Vec<u8> = Vec::new();
+        while let Some(Ok(chunk)) = field.next().await {
+            if data.len() + chunk.len() > limit {
+                return Err(HttpResponse::PayloadTooLarge().json(json!({ "status": 413, "success": false, "error": format!("`{}` is too large. It must be less than {}KB!", name, limit / 1000) })));
+            }
+            data.extend_from_slice(&chunk);
+        }
+        if name == file_field {
+            form.file_name = field.content_disposition().get_filename().map(str::to_string);
+            form.buffer = data;
+        } else {
+            form.fields.insert(name, String::from_utf8_lossy(&data).to_string());
+        }
+    }
+    Ok(form)
+}
+
+// What is left of a multipart texture upload once it passed validation, ready to be stored.
+pub struct TextureUpload {
+    pub file_name: String,
+    pub title: String,
+    pub description: String,
+    pub tags: String,
+    pub category: String,
+    pub buffer: Vec<u8>,
+    pub original: Option<Vec<u8>>,
+    pub hash: String,
+    pub phash: String,
+    pub metadata: SkinMeta,
+    // Textures of other owners this one looks like. It stays out of listings until a moderator
+    // clears it, so the upload response tells the uploader.
+    pub suspected_copy_of: Vec<String>,
+}
+
+// Reads the multipart fields of a skin or cape upload, then decodes, validates and
+// re-encodes the texture. Only the dimension rules and the skin specific conversions
+// differ between the two kinds.
+pub async fn read_texture_upload(
+    client: &Client,
+    payload: &mut Multipart,
+    owner: &str,
+    kind: TextureKind,
+) -> Result<TextureUpload, HttpResponse> {
+    let name = kind.name();
+    let resolutions = match kind {
+        TextureKind::Skin => get_skin_resolutions(),
+        TextureKind::Cape => get_cape_resolutions(),
+    };
+    let max_size = resolutions.iter().map(|resolution| resolution.max_size).max().unwrap_or(0);
+    let max_width = resolutions.iter().map(|resolution| resolution.width as u32).max().unwrap_or(0);
+    let form = read_multipart(payload, name, max_size).await?;
+    let text = |field: &str| form.fields.get(field).cloned().unwrap_or_default();
+    let (title, description, tags, category) = (text("title"), text("description"), text("tags"), text("category"));
+    let model = form.fields.get("model").cloned();
+    let file_name = form.file_name.unwrap_or_else(|| "Unknown file name".to_string());
+    let buffer = form.buffer;
+    let file_size = buffer.len();
+
+    if file_size == 0 || buffer.is_empty() {
+        return Err(HttpResponse::NotFound().json(json!({ "status": 400, "success": false, "error": format!("Could not find {} file.", name) })));
+    }
+
+    if file_size > max_size {
+        return Err(HttpResponse::PayloadTooLarge().json(json!({ "status": 413, "success": false, "error": format!("{} file is too large. It must be less than {}KB!", kind.title(), max_size / 1000) })));
+    }
+
+    let model = match model.as_deref().map(SkinModel::parse) {
+        None => None,
+        Some(Some(model)) => Some(model),
+        Some(None) => {
+            return Err(HttpResponse::BadRequest().json(json!({ "status": 400, "success": false, "error": "Model must be classic or slim!" })));
+        }
+    };
+
+    // Uploads are fully decoded and re-encoded, nothing from the original file is stored as is.
+    let (mut texture, format) = match web::block(move || decode_image(&buffer, max_width)).await {
+        Ok(Some(decoded)) => decoded,
+        Ok(None) => {
+            return Err(HttpResponse::BadRequest().json(json!({ "status": 400, "success": false, "error": format!("{} must be a valid png or jpeg of at most {} pixels wide!", kind.title(), max_width) })));
+        },
+        Err(err) => return Err(HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() }))),
+    };
+    let (width, height) = (texture.width() as usize, texture.height() as usize);
+    let resolution = match kind {
+        TextureKind::Skin => find_resolution(resolutions, width, height),
+        TextureKind::Cape => find_cape_resolution(resolutions, width, height),
+    };
+    match resolution {
+        None => {
+            let allowed = match kind {
+                TextureKind::Skin => describe_resolutions(resolutions),
+                TextureKind::Cape => describe_cape_resolutions(resolutions),
+            };
+            return Err(HttpResponse::BadRequest().json(json!({ "status": 400, "success": false, "error": format!("{} must be {}", kind.title(), allowed) })));
+        },
+        Some(resolution) if file_size > resolution.max_size => {
+            return Err(HttpResponse::PayloadTooLarge().json(json!({ "status": 413, "success": false, "error": format!("{}x{} {}s must be less than {}KB!", width, height, name, resolution.max_size / 1000) })));
+        },
+        Some(_resolution) => {}
+    }
+
+    if format == ImageFormat::Jpeg {
+        apply_jpeg_alpha(&mut texture, kind);
+    }
+    if !has_visible_pixels(&texture) {
+        return Err(HttpResponse::BadRequest().json(json!({ "status": 400, "success": false, "error": format!("{} is completely transparent!", kind.title()) })));
+    }
+
+    let hash = texture_hash(&texture);
+
+    let mut original: Option<Vec<u8>> = None;
+    let metadata = match kind {
+        TextureKind::Skin => {
+            // Legacy 64x32 skins are stored converted to 64x64, the uploaded layout is kept next to it.
+            let legacy = is_legacy(texture.width(), texture.height());
+            if legacy {
+                match encode_png(texture.clone()) {
+                    Ok(encoded) => original = Some(encoded),
+                    Err(err) => {
+                        println!("{} - encoding legacy skin", err);
+                        return Err(HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })));
+                    }
+                }
+                texture = convert_legacy_skin(&texture);
+            }
+
+            // The uploader can override the arm width, otherwise it is guessed from the unused arm pixels.
+            let model = model.unwrap_or_else(|| detect_model(&texture));
+
+            SkinMeta::Image {
+                width,
+                height: texture.height() as usize,
+                content_type: "image/png".to_string(),
+                legacy,
+                model,
+            }
+        },
+        TextureKind::Cape => SkinMeta::Cape {
+            width,
+            height,
+            content_type: "image/png".to_string(),
+        },
+    };
+
+    let phash = perceptual_hash(&texture, kind);
+    let suspected_copy_of = match find_similar_textures(client, &phash, kind, Some(owner)).await {
+        Ok(similar) => similar.into_iter().map(|(id, _distance)| id).collect::<Vec<String>>(),
+        Err(err) => {
+            return Err(HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })));
+        }
+    };
+    if !suspected_copy_of.is_empty() && blocks_near_duplicates() {
+        return Err(HttpResponse::Forbidden().json(json!({ "status": 403, "success": false, "error": format!("{} is too similar to an existing {}!", kind.title(), name) })));
+    }
+
+    let buffer = match encode_png(texture) {
+        Ok(buffer) => buffer,
+        Err(err) => {
+            println!("{} - encoding texture", err);
+            return Err(HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })));
+        }
+    };
+
+    Ok(TextureUpload {
+        file_name,
+        title,
+        description,
+        tags,
+        category,
+        buffer,
+        original,
+        hash,
+        phash,
+        metadata,
+        suspected_copy_of,
+    })
+}
+
+// Writes the current texture of `id`, and the uploaded layout of a converted legacy skin.
+pub fn write_texture_files(id: &str, upload: &TextureUpload) -> std::io::Result<()> {
+    let skins_path = get_skins_path();
+    let legacy_path = format!("{}/{}.legacy.png", skins_path, id);
+    match &upload.original {
+        Some(original) => fs::write(&legacy_path, original)?,
+        None => {
+            if let Err(err) = fs::remove_file(&legacy_path) {
+                if err.kind() != std::io::ErrorKind::NotFound {
+                    return Err(err);
+                }
+            }
+        }
+    }
+    let mut new_file = fs::File::create(format!("{}/{}.png", skins_path, id))?;
+    new_file.write_all(&upload.buffer)
+}
+
 #[put("/upload")]
 pub async fn upload_skin(
+    client: web::Data<Client>,
+    payload: Multipart,
+    req: HttpRequest,
+) -> HttpResponse {
+    upload_texture(client, payload, req, TextureKind::Skin).await
+}
+
+pub async fn upload_texture(
     client: web::Data<Client>,
     mut payload: Multipart,
     req: HttpRequest,
+    kind: TextureKind,
 ) -> HttpResponse {
     if !verified_csrf(&req) {
         return HttpResponse::Unauthorized().json(json!({ "status": 401, "success": false, "error": "Invalid CSRF Token!" }));
@@ -58,103 +677,31 @@ pub async fn upload_skin(
 
         match collection_accounts.find_one(doc! { "session": token }, None).await {
             Ok(Some(account)) => {
-                let mut file_size: usize = 0;
-                let mut buffer: Vec<u8> = Vec::new();
-                let mut file_name: String = "".to_string();
-                let mut title: String = "".to_string();
-                let mut description: String = "".to_string();
-
-                while let Some(Ok(mut field)) = payload.next().await {
-                    if field.name() == "title" {
-                        while let Some(chunk) = field.next().await {
-                            let data = chunk.unwrap();
-                            title = from_utf8(&data.to_vec()).unwrap().to_string();
-                        }
-                    }
-                    if field.name() == "description" {
-                        while let Some(chunk) = field.next().await {
-                            let data = chunk.unwrap();
-                            description = from_utf8(&data.to_vec()).unwrap().to_string();
-                        }
-                    }
-                    if field.name() == "skin" {
-                        while let Some(chunk) = field.next().await {
-                            let data = chunk.unwrap();
-                            file_size += data.len();
-                            buffer.append(&mut data.to_vec());
-                        }
-                        let content_dis = field.content_disposition();
-                        let filename = content_dis.get_filename().ok_or("Unknown file name").unwrap().to_string().to_owned();
-                        file_name = filename;
-                    }
-                }
-
-                if file_size == 0 || buffer.len() == 0 {
-                    return HttpResponse::NotFound().json(json!({ "status": 400, "success": false, "error": "Could not find skin file." }))
-                }
-
-                if file_size > 5000 {
-                    return HttpResponse::PayloadTooLarge().json(json!({ "status": 413, "success": false, "error": "Skin file is too large. It must be less than 5KB!" }))
-                }
-
-                if title.len() > 16 {
-                    return HttpResponse::Forbidden().json(json!({ "status": 403, "success": false, "error": "Title cannot be larger than 16 characters!" }))
-                }
+                let upload = match read_texture_upload(&client, &mut payload, &account.id, kind).await {
+                    Ok(upload) => upload,
+                    Err(response) => return response,
+                };
 
-                if description.len() > 256 {
-                    return HttpResponse::Forbidden().json(json!({ "status": 403, "success": false, "error": "Description cannot be larger than 256 characters!" }))
+                if let Some(response) = check_details(&upload.title, &upload.description) {
+                    return response;
                 }
-
-                let content_type = tree_magic::from_u8(&buffer);
-                let meta = match &content_type[..] {
-                    "image/jpeg" | "image/png" => {
-                        if let Ok(imagesize::ImageSize { width, height }) = imagesize::blob_size(&buffer) {
-                            if height != 32 && height != 64 || width != 64 {
-                                return HttpResponse::Ok().json(json!({ "status": 400, "success": false, "error": "Skin must be 64x64 or 64x32" }));
-                            }
-                            SkinMeta::Image {
-                                width,
-                                height,
-                                content_type: content_type.to_string(),
-                            }
-                        } else {
-                            SkinMeta::Image {
-                                width: 0,
-                                height: 0,
-                                content_type: "".to_string(),
-                            }
-                        }
-                    },
-                    &_ => {
-                        HttpResponse::Ok().json(
-                            json!({ "status": 500, "success": false, "error": "Skin must be a png or jpeg!" }),
-                        );
-                        SkinMeta::Image {
-                            width: 0,
-                            height: 0,
-                            content_type: "".to_string(),
-                        }
-                    }
+                let (tags, category) = match check_tags(&upload.tags, &upload.category) {
+                    Ok(parsed) => parsed,
+                    Err(error) => return HttpResponse::BadRequest().json(json!({ "status": 400, "success": false, "error": error })),
                 };
 
-                // I genuinely have no idea if this hash system will work properly.
-                // By the looks of it, it *should*. But if anyone knows a better way, make a pull request please.
-                let utf8_string = String::from_utf8_lossy(&buffer);
-                let mut hash: String = encrypt(&utf8_string);
-                hash = (&hash[&hash.len() - 48..]).to_string();
-
                 // Checking if the skin already exists by searching the image hash.
-                match collection_skins.find_one(doc! { "hash": &hash }, None).await {
+                match collection_skins.find_one(doc! { "hash": &upload.hash }, None).await {
                     Err(err) => {
                         HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() }))
                     }
                     Ok(Some(_skin)) => {
-                        HttpResponse::Forbidden().json(json!({ "status": 403, "success": false, "error": "Skin file already exists!" }))
+                        HttpResponse::Forbidden().json(json!({ "status": 403, "success": false, "error": format!("{} file already exists!", kind.title()) }))
                     },
                     Ok(None) => {
                         let account_id = &account.id;
                         // Now checking if the title already exists
-                        match collection_skins.find_one(doc! { "owner": account_id.to_string(), "title": { "$regex": "^".to_owned() + &title.to_lowercase() + "$", "$options": "i" } }, None).await {
+                        match collection_skins.find_one(doc! { "owner": account_id.to_string(), "kind": kind.filter(), "title": title_filter(&upload.title) }, None).await {
                             Err(err) => {
                                 HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() }))
                             },
@@ -162,29 +709,40 @@ pub async fn upload_skin(
                                 HttpResponse::Forbidden().json(json!({ "status": 401, "success": false, "error": "Title already exists!" }))
                             }
                             Ok(None) => {
+                                let date = DateTime::now();
                                 let skin = SkinCollection {
-                                    date: DateTime::now(),
+                                    date,
                                     id: Uuid::new_v4().to_string(),
-                                    hash,
-                                    filename: file_name,
-                                    size: file_size.clone(),
-                                    title,
-                                    description,
-                                    metadata: meta,
+                                    kind,
+                                    hash: upload.hash.clone(),
+                                    filename: upload.file_name.clone(),
+                                    size: upload.buffer.len(),
+                                    title: upload.title.clone(),
+                                    description: upload.description.clone(),
+                                    tags,
+                                    category,
+                                    metadata: upload.metadata.clone(),
                                     owner: account_id.to_string(),
+                                    phash_bands: phash_bands(&upload.phash),
+                                    phash: Some(upload.phash.clone()),
+                                    suspected_copy_of: upload.suspected_copy_of.clone(),
+                                    revision: 1,
+                                    revisions: vec![revision_of(1, date, &upload)],
+                                    likes: 0,
+                                    views: 0,
+                                    downloads: 0,
+                                    trending: 0.0,
+                                    comments_locked: false,
                                 };
-                
-                                let skins_path = get_skins_path();
-                                let mut new_file = fs::File::create(format!("{}/{}.png", skins_path, &skin.id)).expect("There was an error creating a skin file!");
-        
-                                match new_file.write_all(&buffer) {
+
+                                match write_texture_files(&skin.id, &upload) {
                                     Ok(()) => {
                                         match collection_skins.insert_one(&skin, None).await {
                                             Ok(_result) => {
-                                                match collection_accounts.update_one(doc! { "id": &account.id }, doc! { "$push": { "skins": &skin.id } }, None).await {
+                                                match collection_accounts.update_one(doc! { "id": &account.id }, doc! { "$push": { kind.account_field(): &skin.id } }, None).await {
                                                     Ok(_result) => {
                                                         HttpResponse::Ok()
-                                                        .json(json!({ "status": 200, "success": true, "skin": &skin.id }))
+                                                        .json(json!({ "status": 200, "success": true, kind.name(): &skin.id, "suspected_copy_of": &skin.suspected_copy_of, "listed": skin.suspected_copy_of.is_empty() }))
                                                     },
                                                     Err(err) => {
                                                         println!("{:?} - Updating user", err);
@@ -199,7 +757,7 @@ pub async fn upload_skin(
                                         }
                                     }
                                     Err(err) => {
-                                        println!("{}", err.to_string());
+                                        println!("{}", err);
                                         HttpResponse::InternalServerError()
                                             .json(json!({ "status": 500, "success": false, "error": err.to_string() }))
                                     }
@@ -0,0 +1,86 @@
+use std::collections::BTreeMap;
+
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use bson::doc;
+use futures_util::stream::StreamExt;
+use mongodb::{bson::DateTime, options::FindOptions, Client, Collection};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{
+    models::{SkinCollection, StatBucket, StatEvent, TextureKind},
+    util::session_account,
+};
+
+const DAY_MILLIS: i64 = 24 * 60 * 60 * 1000;
+
+#[derive(Serialize, Deserialize)]
+pub struct StatsParams {
+    days: Option<i64>,
+    granularity: Option<String>,
+}
+
+#[get("/{id}/stats")]
+pub async fn get_skin_stats(client: web::Data<Client>, id: web::Path<String>, req: HttpRequest, params: web::Query<StatsParams>) -> HttpResponse {
+    let id = id.into_inner();
+    let days = params.days.unwrap_or(30).clamp(1, 90);
+    let step = match params.granularity.as_deref() {
+        None | Some("day") => DAY_MILLIS,
+        Some("hour") => DAY_MILLIS / 24,
+        Some(_) => return HttpResponse::BadRequest().json(json!({ "status": 400, "success": false, "error": "Granularity must be hour or day!" })),
+    };
+
+    let account = match session_account(&client, &req).await {
+        Ok(account) => account,
+        Err(response) => return response,
+    };
+    let skins: Collection<SkinCollection> = client.database("ouja_skins").collection("skins");
+    let skin = match skins.find_one(doc! { "id": &id, "kind": TextureKind::Skin.filter() }, None).await {
+        Ok(Some(skin)) if skin.owner == account.id => skin,
+        Ok(Some(_skin)) => return HttpResponse::Forbidden().json(json!({ "status": 403, "success": false, "error": "You do not own this skin." })),
+        Ok(None) => return HttpResponse::NotFound().json(json!({ "status": 404, "success": false, "error": "Skin not found" })),
+        Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    };
+
+    let now = DateTime::now().timestamp_millis();
+    let since = now - now.rem_euclid(step) - (days * DAY_MILLIS - step);
+    let buckets: Collection<StatBucket> = client.database("ouja_skins").collection("stats");
+    let options = FindOptions::builder().sort(doc! { "bucket": 1 }).build();
+    let mut cursor = match buckets.find(doc! { "skin": &id, "bucket": { "$gte": DateTime::from_millis(since) } }, options).await {
+        Ok(cursor) => cursor,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    };
+
+    // Hourly buckets are summed into days when asked for, and every period of the
+    // range is listed, including the ones without any event.
+    let mut series: BTreeMap<i64, (i64, i64)> = (0..(now - since) / step + 1).map(|i| (since + i * step, (0, 0))).collect();
+    while let Some(bucket) = cursor.next().await {
+        let bucket = match bucket {
+            Ok(bucket) => bucket,
+            Err(err) => {
+                println!("{:?} - collecting stats", err);
+                return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() }));
+            }
+        };
+        let millis = bucket.bucket.timestamp_millis();
+        let entry = series.entry(millis - millis.rem_euclid(step)).or_insert((0, 0));
+        match bucket.event {
+            StatEvent::View => entry.0 += bucket.count,
+            StatEvent::Download => entry.1 += bucket.count,
+        }
+    }
+    let series: Vec<serde_json::Value> = series
+        .into_iter()
+        .map(|(start, (views, downloads))| json!({ "start": DateTime::from_millis(start), "views": views, "downloads": downloads }))
+        .collect();
+
+    HttpResponse::Ok().json(json!({
+        "status": 200,
+        "success": true,
+        "views": skin.views,
+        "downloads": skin.downloads,
+        "likes": skin.likes,
+        "trending": skin.trending,
+        "series": series
+    }))
+}
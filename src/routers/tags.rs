@@ -0,0 +1,160 @@
+use actix_web::{get, web, HttpResponse};
+use bson::{doc, Document};
+use futures_util::stream::StreamExt;
+use mongodb::{options::FindOptions, Client, Collection};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{
+    models::TextureKind,
+    tags::{normalize_tag, parse_category},
+};
+
+use super::skins::RespondSkin;
+
+#[derive(Serialize, Deserialize)]
+pub struct PageParams {
+    page: Option<u64>,
+    limit: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PopularParams {
+    limit: Option<i64>,
+}
+
+#[get("/popular")]
+pub async fn get_popular_tags(client: web::Data<Client>, params: web::Query<PopularParams>) -> HttpResponse {
+    let limit = params.limit.unwrap_or(20).clamp(1, 100);
+    let collection: Collection<Document> = client.database("ouja_skins").collection("skins");
+    let pipeline = vec![
+        doc! { "$match": { "kind": TextureKind::Skin.filter() } },
+        doc! { "$unwind": "$tags" },
+        doc! { "$group": { "_id": "$tags", "count": { "$sum": 1 } } },
+        doc! { "$sort": { "count": -1, "_id": 1 } },
+        doc! { "$limit": limit },
+    ];
+    aggregate_counts(collection, pipeline, "tags").await
+}
+
+#[get("/categories")]
+pub async fn get_categories(client: web::Data<Client>) -> HttpResponse {
+    let collection: Collection<Document> = client.database("ouja_skins").collection("skins");
+    let pipeline = vec![
+        doc! { "$match": { "kind": TextureKind::Skin.filter(), "category": { "$ne": null } } },
+        doc! { "$group": { "_id": "$category", "count": { "$sum": 1 } } },
+        doc! { "$sort": { "count": -1, "_id": 1 } },
+    ];
+    aggregate_counts(collection, pipeline, "categories").await
+}
+
+async fn aggregate_counts(collection: Collection<Document>, pipeline: Vec<Document>, field: &str) -> HttpResponse {
+    match collection.aggregate(pipeline, None).await {
+        Ok(mut cursor) => {
+            let mut results: Vec<serde_json::Value> = Vec::new();
+            while let Some(result) = cursor.next().await {
+                match result {
+                    Ok(result) => {
+                        results.push(json!({ "name": result.get_str("_id").unwrap_or_default(), "count": result.get("count") }));
+                    },
+                    Err(err) => {
+                        println!("{:?} - collecting {}", err, field);
+                        return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() }));
+                    }
+                }
+            }
+            HttpResponse::Ok().json(json!({ "status": 200, "success": true, field: results }))
+        },
+        Err(err) => HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    }
+}
+
+#[get("/{tag}/skins")]
+pub async fn get_tag_skins(client: web::Data<Client>, tag: web::Path<String>, params: web::Query<PageParams>) -> HttpResponse {
+    match normalize_tag(&tag.into_inner()) {
+        Some(tag) => list_skins(client, doc! { "tags": tag }, params.into_inner()).await,
+        None => HttpResponse::NotFound().json(json!({ "status": 404, "success": false, "error": "Tag not found" })),
+    }
+}
+
+#[get("/categories/{category}/skins")]
+pub async fn get_category_skins(client: web::Data<Client>, category: web::Path<String>, params: web::Query<PageParams>) -> HttpResponse {
+    match parse_category(&category.into_inner()) {
+        Ok(Some(category)) => list_skins(client, doc! { "category": category }, params.into_inner()).await,
+        _ => HttpResponse::NotFound().json(json!({ "status": 404, "success": false, "error": "Category not found" })),
+    }
+}
+
+// Deeper pages make the database skip too many documents, search is the way to dig that far.
+const MAX_PAGE: u64 = 1000;
+
+// The page and page size to list, and how many skins come before that page.
+fn page_window(params: &PageParams) -> Result<(u64, i64, u64), String> {
+    let page = params.page.unwrap_or(1).max(1);
+    let limit = params.limit.unwrap_or(24).clamp(1, 100);
+    if page > MAX_PAGE {
+        return Err(format!("Page must be at most {}.", MAX_PAGE));
+    }
+    Ok((page, limit, (page - 1) * limit as u64))
+}
+
+async fn list_skins(client: web::Data<Client>, mut filter: Document, params: PageParams) -> HttpResponse {
+    let (page, limit, skip) = match page_window(&params) {
+        Ok(window) => window,
+        Err(error) => return HttpResponse::BadRequest().json(json!({ "status": 400, "success": false, "error": error })),
+    };
+    filter.insert("kind", TextureKind::Skin.filter());
+
+    let collection: Collection<RespondSkin> = client.database("ouja_skins").collection("skins");
+    let total = match collection.count_documents(filter.clone(), None).await {
+        Ok(total) => total,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    };
+    let options = FindOptions::builder()
+        .sort(doc! { "date": -1, "id": -1 })
+        .skip(skip)
+        .limit(limit)
+        .build();
+
+    match collection.find(filter, options).await {
+        Ok(mut cursor) => {
+            let mut skins: Vec<RespondSkin> = Vec::new();
+            while let Some(skin) = cursor.next().await {
+                match skin {
+                    Ok(skin) => skins.push(skin),
+                    Err(err) => {
+                        println!("{:?} - collecting skins", err);
+                        return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() }));
+                    }
+                }
+            }
+            HttpResponse::Ok().json(json!({ "status": 200, "success": true, "page": page, "limit": limit, "total": total, "skins": skins }))
+        },
+        Err(err) => HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(page: Option<u64>, limit: Option<i64>) -> Result<(u64, i64, u64), String> {
+        page_window(&PageParams { page, limit })
+    }
+
+    #[test]
+    fn skips_the_pages_before_the_requested_one() {
+        assert_eq!(window(None, None), Ok((1, 24, 0)));
+        assert_eq!(window(Some(0), Some(10)), Ok((1, 10, 0)));
+        assert_eq!(window(Some(3), Some(10)), Ok((3, 10, 20)));
+        assert_eq!(window(Some(2), Some(1000)), Ok((2, 100, 100)));
+        assert_eq!(window(Some(2), Some(-5)), Ok((2, 1, 1)));
+    }
+
+    #[test]
+    fn refuses_pages_past_the_maximum() {
+        assert_eq!(window(Some(MAX_PAGE), Some(100)), Ok((MAX_PAGE, 100, (MAX_PAGE - 1) * 100)));
+        assert!(window(Some(MAX_PAGE + 1), None).is_err());
+        assert!(window(Some(u64::MAX), Some(100)).is_err());
+    }
+}
@@ -0,0 +1,248 @@
+use std::{collections::HashSet, fs};
+
+use actix_web::{get, post, web, HttpRequest, HttpResponse};
+use bson::{doc, Regex};
+use futures_util::stream::StreamExt;
+use mongodb::{bson::DateTime, Client, Collection};
+use serde::Deserialize;
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::{
+    models::{Accounts, MinecraftLink, SkinCollection, SkinMeta, SkinModel, StatEvent, TextureKind},
+    stats::spawn_record_hit,
+    texture::is_texture_hash,
+    util::{escape_regex, get_public_url, get_skins_path},
+};
+
+// Resolves the name or UUID a game uses for a player to an account. A UUID can be the
+// account id, a linked Minecraft UUID or an offline mode UUID derived from either name.
+// A verified Minecraft name is proven, so it wins over an account named the same.
+pub async fn resolve_player(client: &Client, player: &str) -> mongodb::error::Result<Option<Accounts>> {
+    let accounts: Collection<Accounts> = client.database("ouja_skins").collection("accounts");
+    if let Ok(uuid) = Uuid::parse_str(player) {
+        let uuid = uuid.to_string();
+        let linked = doc! { "$or": [{ "minecraft.uuid": &uuid }, { "minecraft.offline_uuid": &uuid }] };
+        if let Some(account) = accounts.find_one(linked, None).await? {
+            return Ok(Some(account));
+        }
+        return accounts.find_one(doc! { "$or": [{ "id": &uuid }, { "offline_uuid": &uuid }] }, None).await;
+    }
+    let name = doc! { "$regex": format!("^{}$", escape_regex(player)), "$options": "i" };
+    if let Some(account) = accounts.find_one(doc! { "minecraft.name": name.clone() }, None).await? {
+        return Ok(Some(account));
+    }
+    accounts.find_one(doc! { "username": name }, None).await
+}
+
+// The textures an account wears, skin first. Textures that were removed are left out, and so
+// are textures with a legacy hash that cannot be put in a URL until `backfill-hashes` has run.
+async fn worn_textures(client: &Client, account: &Accounts) -> mongodb::error::Result<Vec<SkinCollection>> {
+    let skins: Collection<SkinCollection> = client.database("ouja_skins").collection("skins");
+    let mut worn = Vec::new();
+    for (kind, active) in [(TextureKind::Skin, &account.active_skin), (TextureKind::Cape, &account.active_cape)] {
+        let Some(id) = active else {
+            continue;
+        };
+        if let Some(texture) = skins.find_one(doc! { "id": id, "kind": kind.filter() }, None).await? {
+            if is_texture_hash(&texture.hash) {
+                worn.push(texture);
+            }
+        }
+    }
+    Ok(worn)
+}
+
+fn skin_model(texture: &SkinCollection) -> SkinModel {
+    match texture.metadata {
+        SkinMeta::Image { model, .. } => model,
+        SkinMeta::Cape { .. } => SkinModel::Classic,
+    }
+}
+
+// Textures are addressed by their content hash, so a game cache that is keyed by the
+// last part of the URL picks up a new texture as soon as the skin changes.
+pub fn texture_url(texture: &SkinCollection) -> String {
+    format!("{}/v1/textures/{}", get_public_url(), texture.hash)
+}
+
+// The look an account currently wears, in the shape of the textures object the
+// Mojang session server puts in a profile. Kinds the account wears nothing of are left out.
+pub async fn texture_profile(client: &Client, account: &Accounts) -> mongodb::error::Result<serde_json::Value> {
+    let mut textures = serde_json::Map::new();
+    for texture in worn_textures(client, account).await? {
+        let entry = match texture.kind {
+            TextureKind::Skin => json!({ "url": texture_url(&texture), "metadata": { "model": skin_model(&texture).mojang_name() } }),
+            TextureKind::Cape => json!({ "url": texture_url(&texture) }),
+        };
+        textures.insert(texture.kind.name().to_uppercase(), entry);
+    }
+    Ok(json!({
+        "timestamp": DateTime::now().timestamp_millis(),
+        "profileId": account.id.replace('-', ""),
+        "profileName": account.username,
+        "textures": textures
+    }))
+}
+
+#[get("/{username}/textures")]
+pub async fn get_user_textures(client: web::Data<Client>, username: web::Path<String>) -> HttpResponse {
+    let accounts: Collection<Accounts> = client.database("ouja_skins").collection("accounts");
+    let account = match accounts.find_one(doc! { "username": { "$regex": format!("^{}$", escape_regex(&username)), "$options": "i" } }, None).await {
+        Ok(Some(account)) => account,
+        Ok(None) => return HttpResponse::NotFound().json(json!({ "status": 404, "success": false, "error": "User not found" })),
+        Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    };
+    match texture_profile(&client, &account).await {
+        Ok(textures) => HttpResponse::Ok().json(textures),
+        Err(err) => HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    }
+}
+
+#[get("/textures/{hash}")]
+pub async fn get_texture_by_hash(client: web::Data<Client>, hash: web::Path<String>, req: HttpRequest) -> HttpResponse {
+    let skins: Collection<SkinCollection> = client.database("ouja_skins").collection("skins");
+    let texture = match skins.find_one(doc! { "hash": hash.into_inner() }, None).await {
+        Ok(Some(texture)) => texture,
+        Ok(None) => return HttpResponse::NotFound().json(json!({ "status": 404, "success": false, "error": "Texture not found" })),
+        Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    };
+    let id = texture.id.clone();
+    match web::block(move || fs::read(format!("{}/{}.png", get_skins_path(), texture.id))).await {
+        Ok(Ok(buffer)) => {
+            spawn_record_hit(client.get_ref().clone(), id, StatEvent::Download, &req);
+            HttpResponse::Ok().content_type("image/png").body(buffer)
+        },
+        Ok(Err(err)) => {
+            println!("{} - reading texture", err);
+            HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() }))
+        },
+        Err(err) => HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    }
+}
+
+// The CustomSkinLoader profile format, textures are fetched from `textures/{hash}` next to it.
+#[get("/{player}.json")]
+pub async fn get_customskinloader_profile(client: web::Data<Client>, player: web::Path<String>) -> HttpResponse {
+    let account = match resolve_player(&client, &player).await {
+        Ok(Some(account)) => account,
+        Ok(None) => return HttpResponse::NotFound().json(json!({ "status": 404, "success": false, "error": "Player not found" })),
+        Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    };
+    let worn = match worn_textures(&client, &account).await {
+        Ok(worn) => worn,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    };
+
+    let mut profile = json!({ "username": account.username });
+    for texture in worn {
+        match texture.kind {
+            TextureKind::Skin => profile["skins"] = json!({ skin_model(&texture).mojang_name(): texture.hash }),
+            TextureKind::Cape => profile["cape"] = json!(texture.hash),
+        }
+    }
+    HttpResponse::Ok().json(profile)
+}
+
+// How many players one lookup can ask for.
+pub const MAX_LOOKUP_PLAYERS: usize = 200;
+
+// An account as the lookup aggregation returns it, with its worn textures joined in.
+#[derive(Deserialize)]
+struct LookupRow {
+    id: String,
+    username: String,
+    offline_uuid: Option<String>,
+    minecraft: Option<MinecraftLink>,
+    #[serde(default)]
+    skin: Vec<SkinCollection>,
+    #[serde(default)]
+    cape: Vec<SkinCollection>,
+}
+
+impl LookupRow {
+    fn matches_minecraft(&self, player: &str) -> bool {
+        let Some(link) = &self.minecraft else {
+            return false;
+        };
+        match Uuid::parse_str(player) {
+            Ok(uuid) => link.uuid == uuid.to_string() || link.offline_uuid == uuid.to_string(),
+            Err(_err) => link.name.eq_ignore_ascii_case(player),
+        }
+    }
+
+    fn matches_account(&self, player: &str) -> bool {
+        match Uuid::parse_str(player) {
+            Ok(uuid) => self.id == uuid.to_string() || self.offline_uuid.as_deref() == Some(uuid.to_string().as_str()),
+            Err(_err) => self.username.eq_ignore_ascii_case(player),
+        }
+    }
+}
+
+// Resolves many players at once for game servers. Every entry is a username or a UUID
+// like `resolve_player` accepts, players that match no account are listed under `missing`.
+#[post("/lookup")]
+pub async fn lookup_profiles(client: web::Data<Client>, players: web::Json<Vec<String>>) -> HttpResponse {
+    let mut seen = HashSet::new();
+    let players: Vec<String> = players.into_inner().into_iter().filter(|player| seen.insert(player.to_lowercase())).collect();
+    if players.len() > MAX_LOOKUP_PLAYERS {
+        return HttpResponse::BadRequest().json(json!({ "status": 400, "success": false, "error": format!("At most {} players can be looked up at once.", MAX_LOOKUP_PLAYERS) }));
+    }
+
+    let mut ids = Vec::new();
+    let mut names = Vec::new();
+    for player in &players {
+        match Uuid::parse_str(player) {
+            Ok(uuid) => ids.push(uuid.to_string()),
+            Err(_err) => names.push(Regex { pattern: format!("^{}$", escape_regex(player)), options: "i".to_string() }),
+        }
+    }
+    let pipeline = vec![
+        doc! { "$match": { "$or": [
+            { "id": { "$in": &ids } },
+            { "offline_uuid": { "$in": &ids } },
+            { "minecraft.uuid": { "$in": &ids } },
+            { "minecraft.offline_uuid": { "$in": &ids } },
+            { "username": { "$in": &names } },
+            { "minecraft.name": { "$in": &names } },
+        ] } },
+        doc! { "$lookup": { "from": "skins", "localField": "active_skin", "foreignField": "id", "as": "skin" } },
+        doc! { "$lookup": { "from": "skins", "localField": "active_cape", "foreignField": "id", "as": "cape" } },
+        doc! { "$project": { "_id": 0, "id": 1, "username": 1, "offline_uuid": 1, "minecraft": 1, "skin": 1, "cape": 1 } },
+    ];
+    let accounts: Collection<Accounts> = client.database("ouja_skins").collection("accounts");
+    let mut cursor = match accounts.aggregate(pipeline, None).await {
+        Ok(cursor) => cursor,
+        Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+    };
+    let mut rows: Vec<LookupRow> = Vec::new();
+    while let Some(row) = cursor.next().await {
+        match row.map(bson::from_document::<LookupRow>) {
+            Ok(Ok(row)) => rows.push(row),
+            Ok(Err(err)) => println!("{:?} - reading looked up account", err),
+            Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+        }
+    }
+
+    let mut profiles = Vec::new();
+    let mut missing = Vec::new();
+    for player in players {
+        // Like `resolve_player`, a verified Minecraft identity wins over an account name.
+        let found = rows.iter().find(|row| row.matches_minecraft(&player)).or_else(|| rows.iter().find(|row| row.matches_account(&player)));
+        let Some(row) = found else {
+            missing.push(player);
+            continue;
+        };
+        let skin = row.skin.first().filter(|skin| is_texture_hash(&skin.hash));
+        profiles.push(json!({
+            "query": player,
+            "id": row.id,
+            "username": row.username,
+            "skin": skin.map(texture_url),
+            "model": skin.map(|skin| skin_model(skin).mojang_name()),
+            "cape": row.cape.first().filter(|cape| is_texture_hash(&cape.hash)).map(texture_url)
+        }));
+    }
+
+    HttpResponse::Ok().json(json!({ "status": 200, "success": true, "profiles": profiles, "missing": missing }))
+}
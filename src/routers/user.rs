@@ -1,37 +1,109 @@
 use actix_web::{get, web, HttpResponse};
-use bson::DateTime;
-use mongodb::{bson::doc, Client, Collection};
+use bson::{DateTime, Document};
+use mongodb::{bson::doc, options::FindOptions, Client, Collection};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 
-use crate::models::Accounts;
+use crate::{
+    cursor::Cursor,
+    models::{Accounts, TextureKind},
+    util::escape_regex,
+};
+
+use super::follows::follow_summary;
 use futures_util::stream::StreamExt;
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct RespondSkin {
-    pub id: String,
-    pub date: DateTime,
-    pub title: String,
-    pub description: String,
-    pub owner: String,
+// Fields a listing can be narrowed down to. The id and date are always
+// returned since the cursor is made from them.
+const LISTING_FIELDS: [&str; 12] = [
+    "id", "date", "title", "description", "owner", "metadata", "tags", "category", "revision", "likes", "views", "downloads",
+];
+
+#[derive(Serialize, Deserialize)]
+pub struct ListingParams {
+    limit: Option<i64>,
+    cursor: Option<String>,
+    fields: Option<String>,
+}
+
+fn listing_projection(fields: Option<&str>) -> Result<Document, String> {
+    let mut projection = doc! { "_id": 0, "id": 1, "date": 1 };
+    let fields: Vec<&str> = match fields {
+        Some(fields) => fields.split(',').map(str::trim).filter(|field| !field.is_empty()).collect(),
+        None => LISTING_FIELDS.to_vec(),
+    };
+    for field in fields {
+        if !LISTING_FIELDS.contains(&field) {
+            return Err(format!("Unknown field `{}`, fields are {}", field, LISTING_FIELDS.join(", ")));
+        }
+        projection.insert(field, 1);
+    }
+    Ok(projection)
 }
 
 #[get("/{username}/skins")]
 pub async fn get_user_skins(
     client: web::Data<Client>,
     username: web::Path<String>,
+    params: web::Query<ListingParams>,
 ) -> HttpResponse {
-    let username = username.into_inner();
+    find_user_textures(client, username.into_inner(), params.into_inner(), TextureKind::Skin).await
+}
+
+#[get("/{username}/capes")]
+pub async fn get_user_capes(
+    client: web::Data<Client>,
+    username: web::Path<String>,
+    params: web::Query<ListingParams>,
+) -> HttpResponse {
+    find_user_textures(client, username.into_inner(), params.into_inner(), TextureKind::Cape).await
+}
+
+async fn find_user_textures(client: web::Data<Client>, username: String, params: ListingParams, kind: TextureKind) -> HttpResponse {
+    let limit = params.limit.unwrap_or(24).clamp(1, 100);
+    let projection = match listing_projection(params.fields.as_deref()) {
+        Ok(projection) => projection,
+        Err(error) => return HttpResponse::BadRequest().json(json!({ "status": 400, "success": false, "error": error })),
+    };
+    let cursor = match &params.cursor {
+        Some(cursor) => match Cursor::decode(cursor) {
+            Some(Cursor::After { sort, date, id }) if sort == "newest" => Some((DateTime::from_millis(date), id)),
+            _ => return HttpResponse::BadRequest().json(json!({ "status": 400, "success": false, "error": "Invalid cursor!" })),
+        },
+        None => None,
+    };
+
     let user_collection: Collection<Accounts> =
         client.database("ouja_skins").collection("accounts");
-    let skin_collection: Collection<RespondSkin> =
+    let skin_collection: Collection<Document> =
         client.database("ouja_skins").collection("skins");
-    match user_collection.find_one(doc! { "username": { "$regex": "^".to_owned() + &username.to_lowercase() + "$", "$options": "i" } }, None).await {
+    match user_collection.find_one(doc! { "username": { "$regex": format!("^{}$", escape_regex(&username)), "$options": "i" } }, None).await {
         Ok(Some(user)) => {
-            let mut skins = skin_collection.find(doc! { "owner": &user.id }, None).await;
-            let mut results: Vec<RespondSkin> = Vec::new();
+            let filter = doc! { "owner": &user.id, "kind": kind.filter() };
+            let total = match skin_collection.count_documents(filter.clone(), None).await {
+                Ok(total) => total,
+                Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+            };
 
-            while let Some(skin) = skins.as_mut().unwrap().next().await {
+            let mut page_filter = filter;
+            if let Some((date, id)) = cursor {
+                page_filter.insert("$or", vec![
+                    doc! { "date": { "$lt": date } },
+                    doc! { "date": date, "id": { "$lt": id } },
+                ]);
+            }
+            let options = FindOptions::builder()
+                .sort(doc! { "date": -1, "id": -1 })
+                .projection(projection)
+                .limit(limit + 1)
+                .build();
+            let mut skins = match skin_collection.find(page_filter, options).await {
+                Ok(skins) => skins,
+                Err(err) => return HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() })),
+            };
+            let mut results: Vec<Document> = Vec::new();
+
+            while let Some(skin) = skins.next().await {
                 match skin {
                     Ok(skin) => {
                         results.push(skin);
@@ -43,7 +115,24 @@ pub async fn get_user_skins(
                 }
             }
 
-            HttpResponse::Ok().json(json!(results))
+            // One extra texture is fetched to know whether there is a next page.
+            let mut next_cursor = None;
+            if results.len() as i64 > limit {
+                results.truncate(limit as usize);
+                if let Some(last) = results.last() {
+                    if let (Ok(date), Ok(id)) = (last.get_datetime("date"), last.get_str("id")) {
+                        next_cursor = Some(Cursor::after("newest", *date, id).encode());
+                    }
+                }
+            }
+
+            HttpResponse::Ok().json(json!({
+                "status": 200,
+                "success": true,
+                kind.account_field(): results,
+                "total": total,
+                "next_cursor": next_cursor
+            }))
         },
         Ok(None) => HttpResponse::NotFound().json(json!({ "status": 404, "success": false, "error": "User not found" })),
         Err(err) => {
@@ -65,11 +154,20 @@ pub async fn index(client: web::Data<Client>, username: web::Path<String>) -> Ht
             .await
         {
             Ok(Some(account)) => {
+                let follows = match follow_summary(&client, &account.id).await {
+                    Ok(follows) => follows,
+                    Err(response) => return response,
+                };
                 let response = json!({
                     "id": account.id,
                     "username": account.username,
                     "about_me": account.about_me,
-                    "profile_picture": account.profile_picture
+                    "profile_picture": account.profile_picture,
+                    "active_skin": account.active_skin,
+                    "active_cape": account.active_cape,
+                    "minecraft": account.minecraft,
+                    "followers": follows["followers"],
+                    "following": follows["following"]
                 });
                 HttpResponse::Ok().json(json!(response))
             },
@@ -0,0 +1,403 @@
+use actix_web::{get, post, web, HttpRequest, HttpResponse};
+use bson::doc;
+use mongodb::{bson::DateTime, Client, Collection};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::{
+    magic_crypt::encrypt,
+    models::{Accounts, YggdrasilJoin, YggdrasilToken},
+    signing::{current_key, published_keys, public_key_der, sign},
+    util::{escape_regex, get_public_url, get_yggdrasil_token_days},
+};
+
+use super::textures::texture_profile;
+
+// How long a server has to confirm a join before the player has to join again.
+const JOIN_SECONDS: i64 = 30;
+
+fn accounts(client: &Client) -> Collection<Accounts> {
+    client.database("ouja_skins").collection("accounts")
+}
+
+fn tokens(client: &Client) -> Collection<YggdrasilToken> {
+    client.database("ouja_skins").collection("yggdrasil_tokens")
+}
+
+fn joins(client: &Client) -> Collection<YggdrasilJoin> {
+    client.database("ouja_skins").collection("yggdrasil_joins")
+}
+
+// Yggdrasil clients expect their own error shape instead of the one the rest of the API uses.
+fn yggdrasil_error(status: u16, error: &str, message: &str) -> HttpResponse {
+    let status = actix_web::http::StatusCode::from_u16(status).unwrap_or(actix_web::http::StatusCode::INTERNAL_SERVER_ERROR);
+    HttpResponse::build(status).json(json!({ "error": error, "errorMessage": message }))
+}
+
+fn invalid_credentials() -> HttpResponse {
+    yggdrasil_error(403, "ForbiddenOperationException", "Invalid credentials. Invalid username or password.")
+}
+
+fn invalid_token() -> HttpResponse {
+    yggdrasil_error(403, "ForbiddenOperationException", "Invalid token.")
+}
+
+fn database_error(err: mongodb::error::Error) -> HttpResponse {
+    server_error(err.to_string())
+}
+
+fn server_error(err: String) -> HttpResponse {
+    println!("{} - yggdrasil", err);
+    yggdrasil_error(500, "InternalServerError", &err)
+}
+
+// Game profiles are identified by the account id without dashes.
+pub fn profile_id(account: &Accounts) -> String {
+    account.id.replace('-', "")
+}
+
+fn account_id(profile_id: &str) -> Option<String> {
+    Uuid::parse_str(profile_id).ok().map(|id| id.to_string())
+}
+
+fn short_profile(account: &Accounts) -> serde_json::Value {
+    json!({ "id": profile_id(account), "name": account.username })
+}
+
+// A full game profile with the textures property the game reads skins from. Servers in
+// online mode only accept the property with a signature.
+pub async fn game_profile(client: &Client, account: &Accounts, signed: bool) -> Result<serde_json::Value, String> {
+    let textures = texture_profile(client, account).await.map_err(|err| err.to_string())?;
+    let value = base64::encode(textures.to_string());
+    let mut property = json!({ "name": "textures", "value": value });
+    if signed {
+        property["signature"] = json!(sign(client, &value).await?);
+    }
+    Ok(json!({
+        "id": profile_id(account),
+        "name": account.username,
+        "properties": [property]
+    }))
+}
+
+async fn find_by_name(client: &Client, name: &str) -> mongodb::error::Result<Option<Accounts>> {
+    accounts(client).find_one(doc! { "username": { "$regex": format!("^{}$", escape_regex(name)), "$options": "i" } }, None).await
+}
+
+// Launchers log in with the email, or the username since `feature.non_email_login` is announced.
+async fn check_credentials(client: &Client, username: &str, password: &str) -> Result<Accounts, HttpResponse> {
+    let found = if username.contains('@') {
+        accounts(client).find_one(doc! { "email": encrypt(&username.to_lowercase()), "password": encrypt(password) }, None).await
+    } else {
+        match find_by_name(client, username).await {
+            Ok(Some(account)) if account.password == encrypt(password) => Ok(Some(account)),
+            Ok(_account) => Ok(None),
+            Err(err) => Err(err),
+        }
+    };
+    match found {
+        Ok(Some(account)) => Ok(account),
+        Ok(None) => Err(invalid_credentials()),
+        Err(err) => Err(database_error(err)),
+    }
+}
+
+// A token that exists, has not expired yet and, when the client sent one, matches its client token.
+async fn find_token(client: &Client, access_token: &str, client_token: Option<&str>) -> Result<YggdrasilToken, HttpResponse> {
+    let mut filter = doc! { "access_token": access_token, "expires": { "$gt": DateTime::now() } };
+    if let Some(client_token) = client_token {
+        filter.insert("client_token", client_token);
+    }
+    match tokens(client).find_one(filter, None).await {
+        Ok(Some(token)) => Ok(token),
+        Ok(None) => Err(invalid_token()),
+        Err(err) => Err(database_error(err)),
+    }
+}
+
+async fn issue_token(client: &Client, account: &Accounts, client_token: String) -> mongodb::error::Result<YggdrasilToken> {
+    let issued = DateTime::now();
+    let token = YggdrasilToken {
+        access_token: Uuid::new_v4().simple().to_string(),
+        client_token,
+        account: account.id.clone(),
+        issued,
+        expires: DateTime::from_millis(issued.timestamp_millis() + get_yggdrasil_token_days() * 24 * 60 * 60 * 1000),
+    };
+    tokens(client).insert_one(&token, None).await?;
+    Ok(token)
+}
+
+// What launchers read from the API root before using any other endpoint.
+pub async fn api_metadata(client: web::Data<Client>) -> HttpResponse {
+    let signing_key = match current_key(&client).await {
+        Ok(key) => key.map(|key| key.public_key),
+        Err(err) => return database_error(err),
+    };
+    let public_url = get_public_url();
+    let host = public_url.split("://").last().unwrap_or_default().split(['/', ':']).next().unwrap_or_default().to_string();
+    HttpResponse::Ok().json(json!({
+        "meta": {
+            "serverName": "Ouja Skins",
+            "implementationName": env!("CARGO_PKG_NAME"),
+            "implementationVersion": env!("CARGO_PKG_VERSION"),
+            "links": { "homepage": public_url },
+            "feature.non_email_login": true
+        },
+        "skinDomains": [host],
+        "signaturePublickey": signing_key
+    }))
+}
+
+// Every key a textures signature can be checked with. Servers that refresh this list keep
+// verifying signatures while a key rotation is in progress.
+#[get("/minecraftservices/publickeys")]
+pub async fn get_public_keys(client: web::Data<Client>) -> HttpResponse {
+    let keys = match published_keys(&client).await {
+        Ok(keys) => keys,
+        Err(err) => return database_error(err),
+    };
+    let mut public_keys = Vec::new();
+    for key in &keys {
+        match public_key_der(key) {
+            Ok(der) => public_keys.push(json!({ "publicKey": der })),
+            Err(err) => return server_error(err),
+        }
+    }
+    HttpResponse::Ok().json(json!({ "profilePropertyKeys": public_keys, "playerCertificateKeys": public_keys }))
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthenticateParams {
+    username: String,
+    password: String,
+    client_token: Option<String>,
+    #[serde(default)]
+    request_user: bool,
+}
+
+#[post("/authserver/authenticate")]
+pub async fn authenticate(client: web::Data<Client>, params: web::Json<AuthenticateParams>) -> HttpResponse {
+    let params = params.into_inner();
+    let account = match check_credentials(&client, &params.username, &params.password).await {
+        Ok(account) => account,
+        Err(response) => return response,
+    };
+    let client_token = params.client_token.filter(|token| !token.is_empty()).unwrap_or_else(|| Uuid::new_v4().simple().to_string());
+
+    // A launcher installation only keeps its latest login.
+    if let Err(err) = tokens(&client).delete_many(doc! { "account": &account.id, "client_token": &client_token }, None).await {
+        return database_error(err);
+    }
+    let token = match issue_token(&client, &account, client_token).await {
+        Ok(token) => token,
+        Err(err) => return database_error(err),
+    };
+
+    let mut response = json!({
+        "accessToken": token.access_token,
+        "clientToken": token.client_token,
+        "availableProfiles": [short_profile(&account)],
+        "selectedProfile": short_profile(&account)
+    });
+    if params.request_user {
+        response["user"] = json!({ "id": profile_id(&account), "properties": [] });
+    }
+    HttpResponse::Ok().json(response)
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RefreshParams {
+    access_token: String,
+    client_token: Option<String>,
+    #[serde(default)]
+    request_user: bool,
+}
+
+// Trades a token for a new one, the old token stops working.
+#[post("/authserver/refresh")]
+pub async fn refresh(client: web::Data<Client>, params: web::Json<RefreshParams>) -> HttpResponse {
+    let params = params.into_inner();
+    let token = match find_token(&client, &params.access_token, params.client_token.as_deref()).await {
+        Ok(token) => token,
+        Err(response) => return response,
+    };
+    let account = match accounts(&client).find_one(doc! { "id": &token.account }, None).await {
+        Ok(Some(account)) => account,
+        Ok(None) => return invalid_token(),
+        Err(err) => return database_error(err),
+    };
+    if let Err(err) = tokens(&client).delete_one(doc! { "access_token": &token.access_token }, None).await {
+        return database_error(err);
+    }
+    let token = match issue_token(&client, &account, token.client_token).await {
+        Ok(token) => token,
+        Err(err) => return database_error(err),
+    };
+
+    let mut response = json!({
+        "accessToken": token.access_token,
+        "clientToken": token.client_token,
+        "selectedProfile": short_profile(&account)
+    });
+    if params.request_user {
+        response["user"] = json!({ "id": profile_id(&account), "properties": [] });
+    }
+    HttpResponse::Ok().json(response)
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenParams {
+    access_token: String,
+    client_token: Option<String>,
+}
+
+#[post("/authserver/validate")]
+pub async fn validate(client: web::Data<Client>, params: web::Json<TokenParams>) -> HttpResponse {
+    match find_token(&client, &params.access_token, params.client_token.as_deref()).await {
+        Ok(_token) => HttpResponse::NoContent().finish(),
+        Err(response) => response,
+    }
+}
+
+// Always succeeds, an unknown token is already as invalid as it gets.
+#[post("/authserver/invalidate")]
+pub async fn invalidate(client: web::Data<Client>, params: web::Json<TokenParams>) -> HttpResponse {
+    match tokens(&client).delete_one(doc! { "access_token": &params.access_token }, None).await {
+        Ok(_result) => HttpResponse::NoContent().finish(),
+        Err(err) => database_error(err),
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SignoutParams {
+    username: String,
+    password: String,
+}
+
+// Ends every launcher login of the account.
+#[post("/authserver/signout")]
+pub async fn signout(client: web::Data<Client>, params: web::Json<SignoutParams>) -> HttpResponse {
+    let account = match check_credentials(&client, &params.username, &params.password).await {
+        Ok(account) => account,
+        Err(response) => return response,
+    };
+    match tokens(&client).delete_many(doc! { "account": &account.id }, None).await {
+        Ok(_result) => HttpResponse::NoContent().finish(),
+        Err(err) => database_error(err),
+    }
+}
+
+// The address without the port the peer address comes with.
+fn client_ip(addr: &str) -> String {
+    match addr.parse::<std::net::SocketAddr>() {
+        Ok(addr) => addr.ip().to_string(),
+        Err(_err) => addr.to_string(),
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JoinParams {
+    access_token: String,
+    selected_profile: String,
+    server_id: String,
+}
+
+// The client side of a server login, the server then confirms it through `has_joined`.
+#[post("/sessionserver/session/minecraft/join")]
+pub async fn join_server(client: web::Data<Client>, req: HttpRequest, params: web::Json<JoinParams>) -> HttpResponse {
+    let params = params.into_inner();
+    let token = match find_token(&client, &params.access_token, None).await {
+        Ok(token) => token,
+        Err(response) => return response,
+    };
+    if account_id(&params.selected_profile).as_deref() != Some(token.account.as_str()) {
+        return invalid_token();
+    }
+
+    let join = YggdrasilJoin {
+        server_id: params.server_id,
+        account: token.account,
+        ip: req.connection_info().realip_remote_addr().map(client_ip),
+        expires: DateTime::from_millis(DateTime::now().timestamp_millis() + JOIN_SECONDS * 1000),
+    };
+    match joins(&client).insert_one(&join, None).await {
+        Ok(_result) => HttpResponse::NoContent().finish(),
+        Err(err) => database_error(err),
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct HasJoinedParams {
+    username: String,
+    #[serde(rename = "serverId")]
+    server_id: String,
+    ip: Option<String>,
+}
+
+// Answers with the player's signed profile when the join happened, an empty 204 otherwise.
+#[get("/sessionserver/session/minecraft/hasJoined")]
+pub async fn has_joined(client: web::Data<Client>, params: web::Query<HasJoinedParams>) -> HttpResponse {
+    let account = match find_by_name(&client, &params.username).await {
+        Ok(Some(account)) => account,
+        Ok(None) => return HttpResponse::NoContent().finish(),
+        Err(err) => return database_error(err),
+    };
+    let filter = doc! { "server_id": &params.server_id, "account": &account.id, "expires": { "$gt": DateTime::now() } };
+    match joins(&client).find_one(filter, None).await {
+        Ok(Some(join)) => {
+            if let (Some(expected), Some(actual)) = (&params.ip, &join.ip) {
+                if expected != actual {
+                    return HttpResponse::NoContent().finish();
+                }
+            }
+        },
+        Ok(None) => return HttpResponse::NoContent().finish(),
+        Err(err) => return database_error(err),
+    }
+    match game_profile(&client, &account, true).await {
+        Ok(profile) => HttpResponse::Ok().json(profile),
+        Err(err) => server_error(err),
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ProfileParams {
+    unsigned: Option<bool>,
+}
+
+// The textures property is only signed when asked for with `?unsigned=false`, like Mojang does.
+#[get("/sessionserver/session/minecraft/profile/{id}")]
+pub async fn get_profile(client: web::Data<Client>, id: web::Path<String>, params: web::Query<ProfileParams>) -> HttpResponse {
+    let Some(id) = account_id(&id) else {
+        return HttpResponse::NoContent().finish();
+    };
+    let account = match accounts(&client).find_one(doc! { "id": id }, None).await {
+        Ok(Some(account)) => account,
+        Ok(None) => return HttpResponse::NoContent().finish(),
+        Err(err) => return database_error(err),
+    };
+    match game_profile(&client, &account, !params.unsigned.unwrap_or(true)).await {
+        Ok(profile) => HttpResponse::Ok().json(profile),
+        Err(err) => server_error(err),
+    }
+}
+
+// Names to profiles, unknown names are left out of the answer.
+#[post("/api/profiles/minecraft")]
+pub async fn lookup_profiles(client: web::Data<Client>, names: web::Json<Vec<String>>) -> HttpResponse {
+    let mut profiles = Vec::new();
+    for name in names.iter().take(10) {
+        match find_by_name(&client, name).await {
+            Ok(Some(account)) => profiles.push(short_profile(&account)),
+            Ok(None) => {},
+            Err(err) => return database_error(err),
+        }
+    }
+    HttpResponse::Ok().json(profiles)
+}
@@ -0,0 +1,109 @@
+use bson::doc;
+use futures_util::stream::StreamExt;
+use mongodb::{bson::DateTime, options::{FindOneOptions, FindOptions}, Client, Collection};
+use rsa::{
+    pkcs8::{DecodePrivateKey, DecodePublicKey, EncodePrivateKey, EncodePublicKey, LineEnding},
+    Pkcs1v15Sign, RsaPrivateKey, RsaPublicKey,
+};
+use sha1::{Digest, Sha1};
+use uuid::Uuid;
+
+use crate::{
+    magic_crypt::{decrypt, encrypt},
+    models::SigningKey,
+    util::get_signing_key_grace_hours,
+};
+
+// Game clients and authlib-injector expect the same key size Mojang uses.
+const KEY_BITS: usize = 4096;
+
+fn signing_keys(client: &Client) -> Collection<SigningKey> {
+    client.database("ouja_skins").collection("signing_keys")
+}
+
+async fn generate_key(activates: DateTime) -> Result<SigningKey, String> {
+    // Generating a key of this size takes a while, it is kept off the async workers.
+    let (private_key, public_key) = actix_web::rt::task::spawn_blocking(|| -> Result<(String, String), String> {
+        let private_key = RsaPrivateKey::new(&mut rand::thread_rng(), KEY_BITS).map_err(|err| err.to_string())?;
+        let public_key = RsaPublicKey::from(&private_key).to_public_key_pem(LineEnding::LF).map_err(|err| err.to_string())?;
+        let private_key = private_key.to_pkcs8_pem(LineEnding::LF).map_err(|err| err.to_string())?;
+        Ok((private_key.to_string(), public_key))
+    })
+    .await
+    .map_err(|err| err.to_string())??;
+
+    Ok(SigningKey {
+        id: Uuid::new_v4().to_string(),
+        private_key: encrypt(&private_key),
+        public_key,
+        created: DateTime::now(),
+        activates,
+        retires: None,
+    })
+}
+
+// Creates the first key when there is none yet, so textures can be signed from the start.
+pub async fn ensure_signing_key(client: &Client) -> Result<(), String> {
+    if signing_keys(client).count_documents(doc! {}, None).await.map_err(|err| err.to_string())? > 0 {
+        return Ok(());
+    }
+    let key = generate_key(DateTime::now()).await?;
+    signing_keys(client).insert_one(&key, None).await.map_err(|err| err.to_string())?;
+    println!("Generated textures signing key {}", key.id);
+    Ok(())
+}
+
+// Adds a new key that only starts signing after the grace period, and retires the current
+// keys a grace period after that. Both keys are published in between, so servers that
+// fetched the key list before or after the rotation can verify every signature they get.
+pub async fn start_key_rotation(client: &Client) -> Result<SigningKey, String> {
+    let grace = get_signing_key_grace_hours() * 60 * 60 * 1000;
+    let activates = DateTime::from_millis(DateTime::now().timestamp_millis() + grace);
+    let key = generate_key(activates).await?;
+
+    let retires = DateTime::from_millis(activates.timestamp_millis() + grace);
+    signing_keys(client)
+        .update_many(doc! { "retires": null }, doc! { "$set": { "retires": retires } }, None)
+        .await
+        .map_err(|err| err.to_string())?;
+    signing_keys(client).insert_one(&key, None).await.map_err(|err| err.to_string())?;
+    Ok(key)
+}
+
+// The key signatures are made with, the most recent one that is active already.
+pub async fn current_key(client: &Client) -> mongodb::error::Result<Option<SigningKey>> {
+    let options = FindOneOptions::builder().sort(doc! { "activates": -1 }).build();
+    signing_keys(client).find_one(doc! { "activates": { "$lte": DateTime::now() } }, options).await
+}
+
+// Every key a signature can currently be checked with, including upcoming and retiring ones.
+pub async fn published_keys(client: &Client) -> mongodb::error::Result<Vec<SigningKey>> {
+    let filter = doc! { "$or": [{ "retires": null }, { "retires": { "$gt": DateTime::now() } }] };
+    let options = FindOptions::builder().sort(doc! { "activates": 1 }).build();
+    let mut cursor = signing_keys(client).find(filter, options).await?;
+    let mut keys = Vec::new();
+    while let Some(key) = cursor.next().await {
+        keys.push(key?);
+    }
+    Ok(keys)
+}
+
+// The public key as base64 DER, the format of Mojang's public key list.
+pub fn public_key_der(key: &SigningKey) -> Result<String, String> {
+    let public_key = RsaPublicKey::from_public_key_pem(&key.public_key).map_err(|err| err.to_string())?;
+    let der = public_key.to_public_key_der().map_err(|err| err.to_string())?;
+    Ok(base64::encode(der.as_bytes()))
+}
+
+// SHA1withRSA signature of a property value, base64 encoded like the game expects it.
+pub async fn sign(client: &Client, value: &str) -> Result<String, String> {
+    let key = match current_key(client).await {
+        Ok(Some(key)) => key,
+        Ok(None) => return Err("No signing key available".to_string()),
+        Err(err) => return Err(err.to_string()),
+    };
+    let private_key = RsaPrivateKey::from_pkcs8_pem(&decrypt(&key.private_key)).map_err(|err| err.to_string())?;
+    let digest = Sha1::digest(value.as_bytes());
+    let signature = private_key.sign(Pkcs1v15Sign::new::<Sha1>(), &digest).map_err(|err| err.to_string())?;
+    Ok(base64::encode(signature))
+}
@@ -0,0 +1,186 @@
+use std::{collections::HashMap, time::Duration};
+
+use actix_web::HttpRequest;
+use bson::{doc, Bson, Document};
+use futures_util::stream::StreamExt;
+use mongodb::{bson::DateTime, options::UpdateOptions, Client, Collection};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    models::{SkinCollection, StatBucket, StatEvent, StatHit},
+    util::{get_stats_dedup_minutes, get_trending_half_life_hours, get_trusted_proxies, is_duplicate_key},
+};
+
+const HOUR_MILLIS: i64 = 60 * 60 * 1000;
+
+// How much one event of each kind adds to the trending score before decay.
+const VIEW_WEIGHT: f64 = 1.0;
+const DOWNLOAD_WEIGHT: f64 = 3.0;
+const LIKE_WEIGHT: f64 = 5.0;
+
+// Events older than this many half lives add less than a percent and are left out.
+const TRENDING_HALF_LIVES: f64 = 7.0;
+
+pub fn hour_bucket(date: DateTime) -> DateTime {
+    let millis = date.timestamp_millis();
+    DateTime::from_millis(millis - millis.rem_euclid(HOUR_MILLIS))
+}
+
+// Identifies a client without storing its address. Forwarded headers are only believed
+// when the connection comes from one of the `TRUSTED_PROXIES`.
+pub fn client_id(req: &HttpRequest) -> String {
+    let peer = req.peer_addr().map(|address| address.ip());
+    let address = match peer {
+        Some(peer) if get_trusted_proxies().contains(&peer) => {
+            let info = req.connection_info();
+            let address = info.realip_remote_addr().unwrap_or_default();
+            // A forwarded address can come with a port that changes between connections.
+            address
+                .parse::<std::net::SocketAddr>()
+                .map(|address| address.ip().to_string())
+                .unwrap_or_else(|_| address.to_string())
+        },
+        Some(peer) => peer.to_string(),
+        None => String::new(),
+    };
+    let user_agent = req.headers().get("user-agent").and_then(|agent| agent.to_str().ok()).unwrap_or_default();
+
+    let mut hasher = Sha256::new();
+    hasher.update(address.as_bytes());
+    hasher.update(b"|");
+    hasher.update(user_agent.as_bytes());
+    hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Counts a view or download unless the same client was counted for the skin within
+// the dedup window. The hit marker is unique per (skin, event, client), so a client
+// sending many requests at once is counted once.
+pub async fn record_hit(client: &Client, skin: &str, event: StatEvent, client_id: String) -> mongodb::error::Result<()> {
+    let database = client.database("ouja_skins");
+    let hits: Collection<StatHit> = database.collection("stat_hits");
+    let now = DateTime::now();
+    let expires = DateTime::from_millis(now.timestamp_millis() + get_stats_dedup_minutes() * 60 * 1000);
+
+    let hit = StatHit { skin: skin.to_string(), event, client: client_id, expires };
+    let counted = match hits.insert_one(&hit, None).await {
+        Ok(_result) => true,
+        Err(err) if is_duplicate_key(&err) => {
+            // The TTL monitor only runs every minute, an expired marker can still be around.
+            let result = hits
+                .update_one(
+                    doc! { "skin": skin, "event": event.name(), "client": &hit.client, "expires": { "$lte": now } },
+                    doc! { "$set": { "expires": expires } },
+                    None,
+                )
+                .await?;
+            result.modified_count > 0
+        },
+        Err(err) => return Err(err),
+    };
+    if !counted {
+        return Ok(());
+    }
+
+    let buckets: Collection<StatBucket> = database.collection("stats");
+    buckets
+        .update_one(
+            doc! { "skin": skin, "event": event.name(), "bucket": hour_bucket(now) },
+            doc! { "$inc": { "count": 1 } },
+            UpdateOptions::builder().upsert(true).build(),
+        )
+        .await?;
+    let skins: Collection<SkinCollection> = database.collection("skins");
+    skins.update_one(doc! { "id": skin }, doc! { "$inc": { event.counter_field(): 1 } }, None).await?;
+    Ok(())
+}
+
+// Records in the background so serving the skin does not wait on it.
+pub fn spawn_record_hit(client: Client, skin: String, event: StatEvent, req: &HttpRequest) {
+    let client_id = client_id(req);
+    actix_web::rt::spawn(async move {
+        if let Err(err) = record_hit(&client, &skin, event, client_id).await {
+            println!("{:?} - recording {} of {}", err, event.name(), skin);
+        }
+    });
+}
+
+// Called when a texture is deleted.
+pub async fn remove_skin_stats(client: &Client, skin: &str) -> mongodb::error::Result<()> {
+    let database = client.database("ouja_skins");
+    database.collection::<StatBucket>("stats").delete_many(doc! { "skin": skin }, None).await?;
+    database.collection::<StatHit>("stat_hits").delete_many(doc! { "skin": skin }, None).await?;
+    Ok(())
+}
+
+// `weight * 0.5 ^ (age / half life)` as an aggregation expression.
+fn decayed(weight: Bson, date_field: &str, now: DateTime, half_life_millis: f64) -> Document {
+    doc! {
+        "$multiply": [
+            weight,
+            { "$pow": [0.5, { "$divide": [{ "$subtract": [now, date_field] }, half_life_millis] }] },
+        ]
+    }
+}
+
+async fn sum_scores(collection: Collection<Document>, pipeline: Vec<Document>, scores: &mut HashMap<String, f64>) -> mongodb::error::Result<()> {
+    let mut cursor = collection.aggregate(pipeline, None).await?;
+    while let Some(result) = cursor.next().await {
+        let result = result?;
+        if let (Ok(skin), Some(score)) = (result.get_str("_id"), result.get("score").and_then(Bson::as_f64)) {
+            *scores.entry(skin.to_string()).or_insert(0.0) += score;
+        }
+    }
+    Ok(())
+}
+
+// Recomputes the trending score of every skin from recent views, downloads and likes.
+// Each event counts for less the older it is, halving every half life.
+pub async fn refresh_trending(client: &Client) -> mongodb::error::Result<usize> {
+    let database = client.database("ouja_skins");
+    let half_life_millis = get_trending_half_life_hours() * HOUR_MILLIS as f64;
+    let now = DateTime::now();
+    let since = DateTime::from_millis(now.timestamp_millis() - (half_life_millis * TRENDING_HALF_LIVES) as i64);
+
+    let mut scores: HashMap<String, f64> = HashMap::new();
+    let event_weight = bson::bson!({ "$multiply": ["$count", { "$cond": [{ "$eq": ["$event", StatEvent::Download.name()] }, DOWNLOAD_WEIGHT, VIEW_WEIGHT] }] });
+    sum_scores(
+        database.collection("stats"),
+        vec![
+            doc! { "$match": { "bucket": { "$gte": since } } },
+            doc! { "$group": { "_id": "$skin", "score": { "$sum": decayed(event_weight, "$bucket", now, half_life_millis) } } },
+        ],
+        &mut scores,
+    )
+    .await?;
+    sum_scores(
+        database.collection("likes"),
+        vec![
+            doc! { "$match": { "date": { "$gte": since } } },
+            doc! { "$group": { "_id": "$skin", "score": { "$sum": decayed(Bson::Double(LIKE_WEIGHT), "$date", now, half_life_millis) } } },
+        ],
+        &mut scores,
+    )
+    .await?;
+
+    let skins: Collection<SkinCollection> = database.collection("skins");
+    let ids: Vec<&String> = scores.keys().collect();
+    skins
+        .update_many(doc! { "trending": { "$gt": 0 }, "id": { "$nin": ids } }, doc! { "$set": { "trending": 0.0 } }, None)
+        .await?;
+    for (skin, score) in &scores {
+        skins.update_one(doc! { "id": skin }, doc! { "$set": { "trending": score } }, None).await?;
+    }
+    Ok(scores.len())
+}
+
+// Keeps the trending scores current while the server runs.
+pub fn spawn_trending_task(client: Client) {
+    actix_web::rt::spawn(async move {
+        loop {
+            if let Err(err) = refresh_trending(&client).await {
+                println!("{:?} - refreshing trending scores", err);
+            }
+            actix_web::rt::time::sleep(Duration::from_secs(60 * 60)).await;
+        }
+    });
+}
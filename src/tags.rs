@@ -0,0 +1,99 @@
+pub const MAX_TAGS: usize = 10;
+pub const MAX_TAG_LENGTH: usize = 24;
+
+// Categories are curated, unlike tags which anyone can make up.
+pub const CATEGORIES: [&str; 16] = [
+    "mob", "animal", "monster", "knight", "medieval", "fantasy", "anime", "cartoon", "game", "movie",
+    "sci-fi", "military", "hero", "villain", "youtuber", "other",
+];
+
+// Tags are lowercase words joined by dashes: "Dark Knight" and "dark_knight" both become "dark-knight".
+pub fn normalize_tag(tag: &str) -> Option<String> {
+    let mut normalized = String::new();
+    for c in tag.trim().chars().flat_map(char::to_lowercase) {
+        if c.is_ascii_alphanumeric() {
+            normalized.push(c);
+        } else if (c == '-' || c == '_' || c.is_whitespace()) && !normalized.ends_with('-') && !normalized.is_empty() {
+            normalized.push('-');
+        }
+    }
+    let normalized = normalized.trim_end_matches('-').to_string();
+    if normalized.len() < 2 || normalized.len() > MAX_TAG_LENGTH {
+        return None;
+    }
+    Some(normalized)
+}
+
+// Parses a comma separated tag list, dropping duplicates.
+pub fn parse_tags(tags: &str) -> Result<Vec<String>, String> {
+    let mut parsed: Vec<String> = Vec::new();
+    for tag in tags.split(',').filter(|tag| !tag.trim().is_empty()) {
+        let normalized = normalize_tag(tag).ok_or(format!(
+            "Tag `{}` must be between 2 and {} letters or numbers!",
+            tag.trim(),
+            MAX_TAG_LENGTH
+        ))?;
+        if !parsed.contains(&normalized) {
+            parsed.push(normalized);
+        }
+    }
+    if parsed.len() > MAX_TAGS {
+        return Err(format!("A skin cannot have more than {} tags!", MAX_TAGS));
+    }
+    Ok(parsed)
+}
+
+// An empty category clears it.
+pub fn parse_category(category: &str) -> Result<Option<String>, String> {
+    let category = category.trim().to_lowercase();
+    if category.is_empty() {
+        return Ok(None);
+    }
+    if CATEGORIES.contains(&&category[..]) {
+        Ok(Some(category))
+    } else {
+        Err(format!("Category must be one of {}!", CATEGORIES.join(", ")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_tags_to_dashed_lowercase_words() {
+        assert_eq!(normalize_tag("Dark Knight").as_deref(), Some("dark-knight"));
+        assert_eq!(normalize_tag(" dark_knight ").as_deref(), Some("dark-knight"));
+        assert_eq!(normalize_tag("--Dark  -_Knight!--").as_deref(), Some("dark-knight"));
+        assert_eq!(normalize_tag("Ünïcode 2").as_deref(), Some("ncode-2"));
+    }
+
+    #[test]
+    fn rejects_tags_of_the_wrong_length() {
+        assert_eq!(normalize_tag("a"), None);
+        assert_eq!(normalize_tag("!!"), None);
+        assert_eq!(normalize_tag(&"a".repeat(MAX_TAG_LENGTH)).map(|tag| tag.len()), Some(MAX_TAG_LENGTH));
+        assert_eq!(normalize_tag(&"a".repeat(MAX_TAG_LENGTH + 1)), None);
+    }
+
+    #[test]
+    fn parses_tag_lists_without_duplicates() {
+        assert_eq!(parse_tags("Knight, dark knight,,knight , Dark_Knight"), Ok(vec!["knight".to_string(), "dark-knight".to_string()]));
+        assert_eq!(parse_tags(" , "), Ok(Vec::new()));
+        assert!(parse_tags("knight,x").is_err());
+    }
+
+    #[test]
+    fn limits_the_number_of_tags() {
+        let tags: Vec<String> = (0..MAX_TAGS).map(|n| format!("tag{}", n)).collect();
+        assert_eq!(parse_tags(&tags.join(",")).map(|tags| tags.len()), Ok(MAX_TAGS));
+        assert!(parse_tags(&format!("{},one-more", tags.join(","))).is_err());
+    }
+
+    #[test]
+    fn parses_curated_categories() {
+        assert_eq!(parse_category(" Sci-Fi "), Ok(Some("sci-fi".to_string())));
+        assert_eq!(parse_category(""), Ok(None));
+        assert!(parse_category("dragons").is_err());
+    }
+}
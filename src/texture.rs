@@ -0,0 +1,603 @@
+use std::io::Cursor;
+
+use image::{
+    codecs::png::{CompressionType, FilterType, PngEncoder},
+    imageops,
+    io::{Limits, Reader},
+    ImageEncoder, ImageError, ImageFormat, RgbaImage,
+};
+use sha2::{Digest, Sha256};
+
+use crate::models::{SkinModel, TextureKind};
+
+// Regions the game copies from the right limbs into the left limb slots when it
+// loads a 64x32 skin: source x, source y, x offset, y offset, width, height.
+// Every copy is mirrored horizontally.
+const LEGACY_LIMB_COPIES: [(u32, u32, i32, i32, u32, u32); 12] = [
+    (4, 16, 16, 32, 4, 4),
+    (8, 16, 16, 32, 4, 4),
+    (0, 20, 24, 32, 4, 12),
+    (4, 20, 16, 32, 4, 12),
+    (8, 20, 8, 32, 4, 12),
+    (12, 20, 16, 32, 4, 12),
+    (44, 16, -8, 32, 4, 4),
+    (48, 16, -8, 32, 4, 4),
+    (40, 20, 0, 32, 4, 12),
+    (44, 20, -8, 32, 4, 12),
+    (48, 20, -16, 32, 4, 12),
+    (52, 20, -8, 32, 4, 12),
+];
+
+pub struct Resolution {
+    pub width: usize,
+    pub max_size: usize,
+}
+
+// Parses a list like `64=5000,128=20000` of allowed skin widths and their byte limits.
+// Widths must be 64 scaled by a power of two.
+pub fn parse_resolutions(config: &str) -> Result<Vec<Resolution>, String> {
+    let mut resolutions: Vec<Resolution> = Vec::new();
+    for entry in config.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+        let (width, max_size) = entry
+            .split_once('=')
+            .ok_or(format!("Resolution `{}` must be written as width=bytes", entry))?;
+        let width: usize = width.trim().parse().map_err(|_| format!("Invalid width in `{}`", entry))?;
+        let max_size: usize = max_size.trim().parse().map_err(|_| format!("Invalid byte limit in `{}`", entry))?;
+        if !width.is_multiple_of(64) || !(width / 64).is_power_of_two() {
+            return Err(format!("Width {} is not 64 scaled by a power of two", width));
+        }
+        resolutions.push(Resolution { width, max_size });
+    }
+    resolutions.sort_by_key(|resolution| resolution.width);
+    Ok(resolutions)
+}
+
+// Skins are either square or, for the legacy layout, twice as wide as they are high.
+pub fn find_resolution(resolutions: &[Resolution], width: usize, height: usize) -> Option<&Resolution> {
+    if height != width && height * 2 != width {
+        return None;
+    }
+    resolutions.iter().find(|resolution| resolution.width == width)
+}
+
+// Capes keep the 64x32 layout at every scale.
+pub fn find_cape_resolution(resolutions: &[Resolution], width: usize, height: usize) -> Option<&Resolution> {
+    if height * 2 != width {
+        return None;
+    }
+    resolutions.iter().find(|resolution| resolution.width == width)
+}
+
+pub fn describe_cape_resolutions(resolutions: &[Resolution]) -> String {
+    resolutions
+        .iter()
+        .map(|resolution| format!("{}x{}", resolution.width, resolution.width / 2))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+pub fn describe_resolutions(resolutions: &[Resolution]) -> String {
+    resolutions
+        .iter()
+        .map(|resolution| format!("{0}x{0} or {0}x{1}", resolution.width, resolution.width / 2))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+pub fn is_legacy(width: u32, height: u32) -> bool {
+    width > 0 && height * 2 == width
+}
+
+// Writes a plain RGBA PNG with only the critical chunks, at the best compression.
+pub fn encode_png(image: RgbaImage) -> Result<Vec<u8>, ImageError> {
+    let mut bytes: Vec<u8> = Vec::new();
+    PngEncoder::new_with_quality(&mut bytes, CompressionType::Best, FilterType::Adaptive).write_image(
+        image.as_raw(),
+        image.width(),
+        image.height(),
+        image::ColorType::Rgba8,
+    )?;
+    Ok(bytes)
+}
+
+// Only PNG and JPEG are accepted, and the file has to decode completely, a valid
+// header in front of something else is not enough. Images wider or higher than
+// `max_side` are refused from their header, before anything gets allocated for them,
+// since a small file can claim enormous dimensions. Decoding is slow, call it from `web::block`.
+pub fn decode_image(buffer: &[u8], max_side: u32) -> Option<(RgbaImage, ImageFormat)> {
+    let format = image::guess_format(buffer).ok()?;
+    if format != ImageFormat::Png && format != ImageFormat::Jpeg {
+        return None;
+    }
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(max_side);
+    limits.max_image_height = Some(max_side);
+    // Room for the decoded pixels and the decoder's own buffers.
+    limits.max_alloc = Some(u64::from(max_side) * u64::from(max_side) * 8);
+    let mut reader = Reader::with_format(Cursor::new(buffer), format);
+    reader.limits(limits);
+    let image = reader.decode().ok()?;
+    Some((image.to_rgba8(), format))
+}
+
+// JPEG has no alpha channel. Capes and the base layer of a skin are opaque anyway,
+// but an opaque overlay would hide the whole base layer, so the overlay regions are cleared.
+pub fn apply_jpeg_alpha(texture: &mut RgbaImage, kind: TextureKind) {
+    for pixel in texture.pixels_mut() {
+        pixel[3] = 255;
+    }
+    if kind == TextureKind::Cape {
+        return;
+    }
+
+    let scale = texture.width() / 64;
+    let mut overlays = vec![(32, 0, 32, 16)];
+    if !is_legacy(texture.width(), texture.height()) {
+        overlays.extend([(0, 32, 64, 16), (0, 48, 16, 16), (48, 48, 16, 16)]);
+    }
+    for (x, y, width, height) in overlays {
+        for j in y * scale..(y + height) * scale {
+            for i in x * scale..(x + width) * scale {
+                texture.get_pixel_mut(i, j).0 = [0, 0, 0, 0];
+            }
+        }
+    }
+}
+
+pub fn has_visible_pixels(texture: &RgbaImage) -> bool {
+    texture.pixels().any(|pixel| pixel[3] > 0)
+}
+
+// Hashes the decoded pixels rather than the file, so the same texture saved by
+// a different editor is still recognised.
+pub fn texture_hash(texture: &RgbaImage) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(texture.width().to_be_bytes());
+    hasher.update(texture.height().to_be_bytes());
+    hasher.update(texture.as_raw());
+    hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+// Whether a stored hash is one `texture_hash` made. Textures stored before it existed carry a
+// base64 hash that can hold `/` and `+`, those have to be rehashed with `backfill-hashes`.
+pub fn is_texture_hash(hash: &str) -> bool {
+    hash.len() == 64 && hash.bytes().all(|byte| byte.is_ascii_hexdigit())
+}
+
+// Converts a 64x32 skin (or an HD multiple of it) to the 64x64 layout the same way the game does.
+pub fn convert_legacy_skin(legacy: &RgbaImage) -> RgbaImage {
+    let scale = legacy.width() / 64;
+    let mut skin = RgbaImage::new(legacy.width(), legacy.width());
+    imageops::replace(&mut skin, legacy, 0, 0);
+
+    for (x, y, dx, dy, width, height) in LEGACY_LIMB_COPIES {
+        let (width, height) = (width * scale, height * scale);
+        let to_x = ((x as i32 + dx) * scale as i32) as u32;
+        let to_y = ((y as i32 + dy) * scale as i32) as u32;
+        for j in 0..height {
+            for i in 0..width {
+                let pixel = *legacy.get_pixel(x * scale + i, y * scale + j);
+                skin.put_pixel(to_x + width - 1 - i, to_y + j, pixel);
+            }
+        }
+    }
+
+    // Old skins often filled the hat layer with a solid colour. Like the game, if the
+    // whole upper right quadrant is opaque the hat is treated as not being there.
+    let opaque = (0..32 * scale).all(|y| (32 * scale..64 * scale).all(|x| skin.get_pixel(x, y)[3] >= 128));
+    if opaque {
+        for y in 0..16 * scale {
+            for x in 32 * scale..64 * scale {
+                skin.get_pixel_mut(x, y)[3] = 0;
+            }
+        }
+    }
+
+    skin
+}
+
+pub fn upgrade_legacy_skin(buffer: &[u8]) -> Result<Vec<u8>, ImageError> {
+    let legacy = image::load_from_memory(buffer)?.to_rgba8();
+    encode_png(convert_legacy_skin(&legacy))
+}
+
+// Slim arms are 3px wide, which leaves the last column of the arm top/bottom and
+// back of the right arm unused. If those are all transparent the skin is slim.
+pub fn detect_model(skin: &RgbaImage) -> SkinModel {
+    let scale = skin.width() / 64;
+    if is_legacy(skin.width(), skin.height()) {
+        return SkinModel::Classic;
+    }
+    let transparent = |xs: std::ops::Range<u32>, ys: std::ops::Range<u32>| {
+        ys.clone().all(|y| xs.clone().all(|x| skin.get_pixel(x, y)[3] == 0))
+    };
+    if transparent(50 * scale..52 * scale, 16 * scale..20 * scale) && transparent(54 * scale..56 * scale, 20 * scale..32 * scale) {
+        SkinModel::Slim
+    } else {
+        SkinModel::Classic
+    }
+}
+
+// Number of 16 bit bands the perceptual hash is split into. Two hashes that differ in
+// fewer bits than there are bands share at least one band, which is what gets indexed.
+pub const PHASH_BANDS: usize = 16;
+
+// Parts of the 64x64 skin layout the game draws, as x, y, width, height: head and hat,
+// then the right leg, body and right arm with their overlays, then the left limbs.
+const SKIN_REGIONS: [(u32, u32, u32, u32); 24] = [
+    (8, 0, 16, 8),
+    (0, 8, 32, 8),
+    (40, 0, 16, 8),
+    (32, 8, 32, 8),
+    (4, 16, 8, 4),
+    (0, 20, 16, 12),
+    (20, 16, 16, 4),
+    (16, 20, 24, 12),
+    (44, 16, 8, 4),
+    (40, 20, 16, 12),
+    (4, 32, 8, 4),
+    (0, 36, 16, 12),
+    (20, 32, 16, 4),
+    (16, 36, 24, 12),
+    (44, 32, 8, 4),
+    (40, 36, 16, 12),
+    (4, 48, 8, 4),
+    (0, 52, 16, 12),
+    (20, 48, 8, 4),
+    (16, 52, 16, 12),
+    (36, 48, 8, 4),
+    (32, 52, 16, 12),
+    (52, 48, 8, 4),
+    (48, 52, 16, 12),
+];
+
+// The cape and the elytra of the 64x32 cape layout.
+const CAPE_REGIONS: [(u32, u32, u32, u32); 2] = [(0, 0, 22, 17), (22, 0, 24, 22)];
+
+// A 256 bit hash over a 16x16 grid of blocks, 4x4 pixels on the 64x64 skin layout and 4x2
+// on the 64x32 cape layout. Only blocks in parts the game draws that have visible pixels
+// take part: each is set when its alpha weighted luminance is above their mean. Padding
+// and empty blocks stay unset, so they do not make every texture of a layout look alike.
+pub fn perceptual_hash(texture: &RgbaImage, kind: TextureKind) -> String {
+    let (height, regions): (u32, &[(u32, u32, u32, u32)]) = match kind {
+        TextureKind::Skin => (64, &SKIN_REGIONS),
+        TextureKind::Cape => (32, &CAPE_REGIONS),
+    };
+    let texture = if kind == TextureKind::Skin && is_legacy(texture.width(), texture.height()) {
+        convert_legacy_skin(texture)
+    } else {
+        texture.clone()
+    };
+    let texture = if texture.width() == 64 && texture.height() == height {
+        texture
+    } else {
+        imageops::resize(&texture, 64, height, imageops::FilterType::Nearest)
+    };
+    let (block_width, block_height) = (4, height / 16);
+
+    let mut blocks: Vec<Option<f32>> = Vec::with_capacity(256);
+    for row in 0..16 {
+        for column in 0..16 {
+            let (x, y) = (column * block_width, row * block_height);
+            let drawn = regions
+                .iter()
+                .any(|&(left, top, width, height)| x < left + width && left < x + block_width && y < top + height && top < y + block_height);
+            let mut luminance = 0.0;
+            let mut weight = 0.0;
+            if drawn {
+                for j in y..y + block_height {
+                    for i in x..x + block_width {
+                        let pixel = texture.get_pixel(i, j);
+                        let alpha = pixel[3] as f32 / 255.0;
+                        luminance += (0.299 * pixel[0] as f32 + 0.587 * pixel[1] as f32 + 0.114 * pixel[2] as f32) * alpha;
+                        weight += alpha;
+                    }
+                }
+            }
+            blocks.push((weight > 0.0).then(|| luminance / weight));
+        }
+    }
+
+    let used: Vec<f32> = blocks.iter().flatten().copied().collect();
+    let mean = used.iter().sum::<f32>() / used.len().max(1) as f32;
+
+    let mut bits = [0u8; 32];
+    for (i, value) in blocks.iter().enumerate() {
+        if value.is_some_and(|value| value > mean) {
+            bits[i / 8] |= 1 << (7 - i % 8);
+        }
+    }
+    bits.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub fn phash_bands(phash: &str) -> Vec<String> {
+    let width = phash.len() / PHASH_BANDS;
+    (0..PHASH_BANDS)
+        .filter_map(|band| Some(format!("{}:{}", band, phash.get(band * width..(band + 1) * width)?)))
+        .collect()
+}
+
+pub fn phash_distance(a: &str, b: &str) -> Option<u32> {
+    if a.len() != b.len() {
+        return None;
+    }
+    let mut distance = 0;
+    for (a, b) in a.as_bytes().chunks(2).zip(b.as_bytes().chunks(2)) {
+        let a = u8::from_str_radix(std::str::from_utf8(a).ok()?, 16).ok()?;
+        let b = u8::from_str_radix(std::str::from_utf8(b).ok()?, 16).ok()?;
+        distance += (a ^ b).count_ones();
+    }
+    Some(distance)
+}
+
+// Square sizes every profile picture is stored in.
+pub const AVATAR_SIZES: [u32; 4] = [32, 64, 128, 256];
+
+// Cuts the largest centred square out of an image.
+pub fn crop_square(image: &RgbaImage) -> RgbaImage {
+    let side = image.width().min(image.height());
+    let x = (image.width() - side) / 2;
+    let y = (image.height() - side) / 2;
+    imageops::crop_imm(image, x, y, side, side).to_image()
+}
+
+// The front of the head with the hat layer drawn over it, at the skin's own scale.
+pub fn skin_face(skin: &RgbaImage) -> RgbaImage {
+    let scale = skin.width() / 64;
+    let mut face = imageops::crop_imm(skin, 8 * scale, 8 * scale, 8 * scale, 8 * scale).to_image();
+    for pixel in face.pixels_mut() {
+        pixel[3] = 255;
+    }
+    let hat = imageops::crop_imm(skin, 40 * scale, 8 * scale, 8 * scale, 8 * scale).to_image();
+    imageops::overlay(&mut face, &hat, 0, 0);
+    face
+}
+
+// Scales a square picture to every avatar size. Pixel art like a skin face is scaled
+// without smoothing so it stays sharp, pictures are filtered.
+pub fn avatar_variants(square: &RgbaImage, pixel_art: bool) -> Vec<(u32, RgbaImage)> {
+    let filter = if pixel_art { imageops::FilterType::Nearest } else { imageops::FilterType::Lanczos3 };
+    AVATAR_SIZES
+        .iter()
+        .map(|&size| (size, imageops::resize(square, size, size, filter)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgba;
+
+    use super::*;
+
+    fn fill(texture: &mut RgbaImage, (x, y, width, height): (u32, u32, u32, u32), color: impl Fn(u32, u32) -> [u8; 4]) {
+        for j in y..y + height {
+            for i in x..x + width {
+                texture.put_pixel(i, j, Rgba(color(i, j)));
+            }
+        }
+    }
+
+    // Dark hair over a light face, a shaded cyan shirt, blue trousers and bare arms.
+    fn steve() -> RgbaImage {
+        let mut skin = RgbaImage::new(64, 64);
+        fill(&mut skin, (0, 0, 32, 16), |_x, y| if y < 10 { [60, 40, 20, 255] } else { [200, 150, 120, 255] });
+        fill(&mut skin, (10, 12, 2, 1), |_x, _y| [255, 255, 255, 255]);
+        fill(&mut skin, (16, 16, 24, 16), |x, _y| [0, 150 + (x % 8) as u8 * 10, 170, 255]);
+        fill(&mut skin, (0, 16, 16, 16), |_x, y| [40, 40, 120 + (y % 4) as u8 * 20, 255]);
+        fill(&mut skin, (16, 48, 16, 16), |_x, y| [40, 40, 120 + (y % 4) as u8 * 20, 255]);
+        fill(&mut skin, (40, 16, 16, 16), |_x, y| if y < 24 { [0, 170, 170, 255] } else { [200, 150, 120, 255] });
+        fill(&mut skin, (32, 48, 16, 16), |_x, y| if y < 56 { [0, 170, 170, 255] } else { [200, 150, 120, 255] });
+        skin
+    }
+
+    // A light mottled body with a dark face and striped legs, drawn over the whole layout.
+    fn creeper() -> RgbaImage {
+        let mut skin = RgbaImage::new(64, 64);
+        fill(&mut skin, (0, 0, 64, 64), |x, y| {
+            let noise = ((x * 7 + y * 13) ^ (x * y)) % 5 * 20;
+            [80 + noise as u8, 200 - noise as u8, 80, 255]
+        });
+        fill(&mut skin, (0, 8, 32, 8), |x, _y| if x % 8 < 4 { [10, 30, 10, 255] } else { [30, 60, 30, 255] });
+        fill(&mut skin, (0, 16, 16, 16), |x, _y| if x % 4 < 2 { [20, 20, 20, 255] } else { [230, 230, 230, 255] });
+        fill(&mut skin, (16, 48, 16, 16), |x, _y| if x % 4 < 2 { [230, 230, 230, 255] } else { [20, 20, 20, 255] });
+        skin
+    }
+
+    fn distance(a: &RgbaImage, b: &RgbaImage, kind: TextureKind) -> u32 {
+        phash_distance(&perceptual_hash(a, kind), &perceptual_hash(b, kind)).unwrap()
+    }
+
+    #[test]
+    fn different_skins_do_not_share_a_band() {
+        assert!(distance(&steve(), &creeper(), TextureKind::Skin) > PHASH_BANDS as u32);
+    }
+
+    #[test]
+    fn recoloured_copy_stays_within_the_threshold() {
+        let mut copy = steve();
+        for pixel in copy.pixels_mut() {
+            pixel.0 = [(pixel[0] as f32 * 0.8) as u8 + 20, (pixel[1] as f32 * 0.8) as u8 + 20, (pixel[2] as f32 * 0.8) as u8 + 20, pixel[3]];
+        }
+        assert!(distance(&steve(), &copy, TextureKind::Skin) < PHASH_BANDS as u32);
+    }
+
+    #[test]
+    fn padding_does_not_count() {
+        let mut padded = steve();
+        fill(&mut padded, (0, 0, 8, 8), |_x, _y| [255, 255, 255, 255]);
+        fill(&mut padded, (56, 16, 8, 16), |_x, _y| [255, 0, 0, 255]);
+        assert_eq!(perceptual_hash(&steve(), TextureKind::Skin), perceptual_hash(&padded, TextureKind::Skin));
+
+        let mut only_padding = RgbaImage::new(64, 64);
+        fill(&mut only_padding, (0, 0, 8, 8), |_x, _y| [255, 255, 255, 255]);
+        assert_eq!(perceptual_hash(&only_padding, TextureKind::Skin), "0".repeat(64));
+    }
+
+    #[test]
+    fn hd_and_legacy_skins_hash_like_their_64px_layout() {
+        let hd = imageops::resize(&steve(), 128, 128, imageops::FilterType::Nearest);
+        assert_eq!(perceptual_hash(&hd, TextureKind::Skin), perceptual_hash(&steve(), TextureKind::Skin));
+
+        let legacy = imageops::crop_imm(&creeper(), 0, 0, 64, 32).to_image();
+        assert_eq!(perceptual_hash(&legacy, TextureKind::Skin), perceptual_hash(&convert_legacy_skin(&legacy), TextureKind::Skin));
+    }
+
+    #[test]
+    fn capes_are_hashed_on_their_own_layout() {
+        let mut red = RgbaImage::new(64, 32);
+        fill(&mut red, (0, 0, 22, 17), |x, y| if (x + y) % 6 < 3 { [200, 30, 30, 255] } else { [90, 10, 10, 255] });
+        let mut blue = RgbaImage::new(64, 32);
+        fill(&mut blue, (0, 0, 22, 17), |_x, y| if y < 8 { [30, 30, 220, 255] } else { [240, 240, 240, 255] });
+        assert!(distance(&red, &blue, TextureKind::Cape) > PHASH_BANDS as u32);
+        // Nothing of the cape is outside the grid cells the hash looks at.
+        assert_ne!(perceptual_hash(&blue, TextureKind::Cape), "0".repeat(64));
+    }
+
+    #[test]
+    fn splits_the_hash_into_indexed_bands() {
+        let phash = perceptual_hash(&steve(), TextureKind::Skin);
+        let bands = phash_bands(&phash);
+        assert_eq!(bands.len(), PHASH_BANDS);
+        assert_eq!(bands[0], format!("0:{}", &phash[0..4]));
+        assert_eq!(bands[15], format!("15:{}", &phash[60..64]));
+    }
+
+    #[test]
+    fn measures_the_distance_between_hashes() {
+        assert_eq!(phash_distance("00ff", "00ff"), Some(0));
+        assert_eq!(phash_distance("00ff", "01fe"), Some(2));
+        assert_eq!(phash_distance("00", "0000"), None);
+        assert_eq!(phash_distance("zz", "00"), None);
+    }
+
+    // Every pixel of the legacy layout gets its own colour, so a copy shows where it came from.
+    fn numbered_legacy(scale: u32) -> RgbaImage {
+        let mut legacy = RgbaImage::new(64 * scale, 32 * scale);
+        fill(&mut legacy, (0, 0, 64 * scale, 32 * scale), |x, y| [x as u8, y as u8, 100, 255]);
+        fill(&mut legacy, (32 * scale, 0, 32 * scale, 16 * scale), |_x, _y| [0, 0, 0, 0]);
+        legacy
+    }
+
+    #[test]
+    fn legacy_limb_copies_fill_the_left_limb_slots() {
+        let mut covered = RgbaImage::new(64, 64);
+        for (x, y, dx, dy, width, height) in LEGACY_LIMB_COPIES {
+            assert!(x + width <= 64 && y + height <= 32);
+            let (to_x, to_y) = ((x as i32 + dx) as u32, (y as i32 + dy) as u32);
+            assert!(to_y >= 48 && to_x + width <= 64 && to_y + height <= 64);
+            fill(&mut covered, (to_x, to_y, width, height), |_x, _y| [0, 0, 0, 255]);
+        }
+        // The left leg and left arm base layers, nothing else of the 64x64 layout.
+        for (x, y, width, height) in [(20, 48, 8, 4), (16, 52, 16, 12), (36, 48, 8, 4), (32, 52, 16, 12)] {
+            assert!((y..y + height).all(|j| (x..x + width).all(|i| covered.get_pixel(i, j)[3] == 255)));
+        }
+        assert_eq!(covered.pixels().filter(|pixel| pixel[3] == 255).count(), 2 * (8 * 4 + 16 * 12));
+    }
+
+    #[test]
+    fn converts_legacy_skins_like_the_game() {
+        let legacy = numbered_legacy(1);
+        let skin = convert_legacy_skin(&legacy);
+        assert_eq!(skin.dimensions(), (64, 64));
+        // The top half is kept as is and the new overlay slots stay empty.
+        assert_eq!(skin.get_pixel(10, 10), legacy.get_pixel(10, 10));
+        assert_eq!(skin.get_pixel(4, 36)[3], 0);
+        assert_eq!(skin.get_pixel(56, 56)[3], 0);
+        // The front of the right leg is mirrored onto the front of the left leg.
+        for j in 0..12 {
+            for i in 0..4 {
+                assert_eq!(skin.get_pixel(20 + 3 - i, 52 + j), legacy.get_pixel(4 + i, 20 + j));
+            }
+        }
+        // The back of the right arm becomes the back of the left arm.
+        assert_eq!(skin.get_pixel(47, 52), legacy.get_pixel(52, 20));
+    }
+
+    #[test]
+    fn converts_hd_legacy_skins_at_their_scale() {
+        let legacy = numbered_legacy(2);
+        let skin = convert_legacy_skin(&legacy);
+        assert_eq!(skin.dimensions(), (128, 128));
+        assert_eq!(skin.get_pixel(2 * 20 + 7, 2 * 52), legacy.get_pixel(2 * 4, 2 * 20));
+        assert_eq!(skin.get_pixel(2 * 20, 2 * 52 + 1), legacy.get_pixel(2 * 4 + 7, 2 * 20 + 1));
+    }
+
+    #[test]
+    fn solid_legacy_hats_are_cleared() {
+        let mut legacy = numbered_legacy(1);
+        fill(&mut legacy, (32, 0, 32, 16), |_x, _y| [200, 0, 0, 255]);
+        let skin = convert_legacy_skin(&legacy);
+        assert!((0..16).all(|y| (32..64).all(|x| skin.get_pixel(x, y)[3] == 0)));
+
+        // One see-through pixel is enough for the hat to be drawn.
+        legacy.put_pixel(63, 0, Rgba([0, 0, 0, 0]));
+        let skin = convert_legacy_skin(&legacy);
+        assert_eq!(skin.get_pixel(40, 8), &Rgba([200, 0, 0, 255]));
+    }
+
+    #[test]
+    fn upgrades_stored_legacy_files() {
+        let legacy = numbered_legacy(1);
+        let upgraded = upgrade_legacy_skin(&encode_png(legacy.clone()).unwrap()).unwrap();
+        assert_eq!(image::load_from_memory(&upgraded).unwrap().to_rgba8(), convert_legacy_skin(&legacy));
+        assert!(upgrade_legacy_skin(b"not a png").is_err());
+    }
+
+    #[test]
+    fn detects_slim_arms_from_the_unused_pixels() {
+        let mut classic = RgbaImage::new(64, 64);
+        fill(&mut classic, (40, 16, 16, 16), |_x, _y| [0, 170, 170, 255]);
+        assert_eq!(detect_model(&classic), SkinModel::Classic);
+
+        let mut slim = classic.clone();
+        fill(&mut slim, (50, 16, 2, 4), |_x, _y| [0, 0, 0, 0]);
+        fill(&mut slim, (54, 20, 2, 12), |_x, _y| [0, 0, 0, 0]);
+        assert_eq!(detect_model(&slim), SkinModel::Slim);
+
+        // A single painted pixel in the unused columns means the arm is 4px wide.
+        slim.put_pixel(55, 31, Rgba([0, 170, 170, 255]));
+        assert_eq!(detect_model(&slim), SkinModel::Classic);
+    }
+
+    #[test]
+    fn detects_slim_arms_on_hd_skins() {
+        let mut slim = RgbaImage::new(128, 128);
+        fill(&mut slim, (80, 32, 32, 32), |_x, _y| [0, 170, 170, 255]);
+        fill(&mut slim, (100, 32, 4, 8), |_x, _y| [0, 0, 0, 0]);
+        fill(&mut slim, (108, 40, 4, 24), |_x, _y| [0, 0, 0, 0]);
+        assert_eq!(detect_model(&slim), SkinModel::Slim);
+    }
+
+    #[test]
+    fn legacy_skins_are_always_classic() {
+        assert_eq!(detect_model(&RgbaImage::new(64, 32)), SkinModel::Classic);
+    }
+
+    #[test]
+    fn parses_resolutions_sorted_by_width() {
+        let resolutions = parse_resolutions(" 128=20000, 64=5000,,").unwrap();
+        let parsed: Vec<(usize, usize)> = resolutions.iter().map(|resolution| (resolution.width, resolution.max_size)).collect();
+        assert_eq!(parsed, vec![(64, 5000), (128, 20000)]);
+        assert!(parse_resolutions("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_malformed_resolutions() {
+        assert!(parse_resolutions("64").is_err());
+        assert!(parse_resolutions("wide=5000").is_err());
+        assert!(parse_resolutions("64=lots").is_err());
+        assert!(parse_resolutions("64=-1").is_err());
+    }
+
+    #[test]
+    fn only_accepts_64_scaled_by_a_power_of_two() {
+        assert!(parse_resolutions("64=1,128=1,256=1,512=1").is_ok());
+        for width in ["0", "32", "96", "192", "100"] {
+            assert!(parse_resolutions(&format!("{}=5000", width)).is_err(), "{} was accepted", width);
+        }
+    }
+}
@@ -1,11 +1,135 @@
-use actix_web::HttpRequest;
+use std::{collections::HashMap, net::IpAddr, sync::OnceLock};
 
-use crate::magic_crypt::decrypt;
+use actix_web::{HttpRequest, HttpResponse};
+use bson::doc;
+use futures_util::stream::StreamExt;
+use md5::{Digest, Md5};
+use mongodb::{
+    error::{ErrorKind, WriteFailure},
+    Client, Collection,
+};
+use serde_json::json;
+
+use crate::{
+    magic_crypt::decrypt,
+    models::Accounts,
+    texture::{parse_resolutions, Resolution, PHASH_BANDS},
+};
 
 pub fn get_skins_path() -> String {
     return dotenvy::var("SKINS_PATH").unwrap();
 }
 
+static SKIN_RESOLUTIONS: OnceLock<Vec<Resolution>> = OnceLock::new();
+
+// Parses SKIN_RESOLUTIONS once at startup, so a bad value stops the server before it takes uploads.
+pub fn load_skin_resolutions() -> Result<(), String> {
+    let config = dotenvy::var("SKIN_RESOLUTIONS").unwrap_or_else(|_| "64=5000".into());
+    let resolutions = parse_resolutions(&config).map_err(|err| format!("Invalid SKIN_RESOLUTIONS: {}", err))?;
+    SKIN_RESOLUTIONS.get_or_init(|| resolutions);
+    Ok(())
+}
+
+pub fn get_skin_resolutions() -> &'static [Resolution] {
+    SKIN_RESOLUTIONS.get().map(Vec::as_slice).unwrap_or_default()
+}
+
+static CAPE_RESOLUTIONS: OnceLock<Vec<Resolution>> = OnceLock::new();
+
+// Parses CAPE_RESOLUTIONS once at startup, like `load_skin_resolutions`.
+pub fn load_cape_resolutions() -> Result<(), String> {
+    let config = dotenvy::var("CAPE_RESOLUTIONS").unwrap_or_else(|_| "64=5000".into());
+    let resolutions = parse_resolutions(&config).map_err(|err| format!("Invalid CAPE_RESOLUTIONS: {}", err))?;
+    CAPE_RESOLUTIONS.get_or_init(|| resolutions);
+    Ok(())
+}
+
+pub fn get_cape_resolutions() -> &'static [Resolution] {
+    CAPE_RESOLUTIONS.get().map(Vec::as_slice).unwrap_or_default()
+}
+
+// Maximum number of differing perceptual hash bits for two textures to count as near duplicates.
+pub fn get_phash_threshold() -> u32 {
+    let threshold = dotenvy::var("PHASH_THRESHOLD").ok().and_then(|threshold| threshold.parse().ok()).unwrap_or(12);
+    threshold.min(PHASH_BANDS as u32 - 1)
+}
+
+// Near duplicates are only flagged for moderators unless PHASH_MODE is set to `block`.
+pub fn blocks_near_duplicates() -> bool {
+    dotenvy::var("PHASH_MODE").map(|mode| mode == "block").unwrap_or(false)
+}
+
+// How long the same client viewing or downloading a skin again is not counted, in minutes.
+pub fn get_stats_dedup_minutes() -> i64 {
+    dotenvy::var("STATS_DEDUP_MINUTES").ok().and_then(|minutes| minutes.parse().ok()).unwrap_or(30).max(1)
+}
+
+// Addresses of reverse proxies whose forwarded headers name the real client, comma separated.
+// Without it the connecting address is used, anyone could put anything in those headers.
+pub fn get_trusted_proxies() -> Vec<IpAddr> {
+    dotenvy::var("TRUSTED_PROXIES").unwrap_or_default().split(',').filter_map(|proxy| proxy.trim().parse().ok()).collect()
+}
+
+// After this many hours an event only counts half towards the trending score.
+pub fn get_trending_half_life_hours() -> f64 {
+    dotenvy::var("TRENDING_HALF_LIFE_HOURS").ok().and_then(|hours| hours.parse().ok()).unwrap_or(24.0f64).max(1.0)
+}
+
+// Notifications are removed automatically after this many days.
+pub fn get_notification_ttl_days() -> i64 {
+    dotenvy::var("NOTIFICATION_TTL_DAYS").ok().and_then(|days| days.parse().ok()).unwrap_or(90).max(1)
+}
+
+// Address the API is reachable at from outside, used for absolute texture URLs.
+pub fn get_public_url() -> String {
+    dotenvy::var("PUBLIC_URL").map(|url| url.trim_end_matches('/').to_string()).unwrap_or_default()
+}
+
+pub fn get_yggdrasil_token_days() -> i64 {
+    dotenvy::var("YGGDRASIL_TOKEN_DAYS").ok().and_then(|days| days.parse().ok()).unwrap_or(15).max(1)
+}
+
+// How long a rotated signing key is published before it signs, and the old one after it stopped.
+pub fn get_signing_key_grace_hours() -> i64 {
+    dotenvy::var("SIGNING_KEY_GRACE_HOURS").ok().and_then(|hours| hours.parse().ok()).unwrap_or(24).max(0)
+}
+
+// Base URL of the Mojang API that resolves player names to UUIDs.
+pub fn get_minecraft_api_url() -> String {
+    dotenvy::var("MINECRAFT_API_URL").map(|url| url.trim_end_matches('/').to_string()).unwrap_or_else(|_| "https://api.mojang.com".into())
+}
+
+// Base URL of the Mojang session server that returns a player's profile and skin.
+pub fn get_minecraft_session_url() -> String {
+    dotenvy::var("MINECRAFT_SESSION_URL").map(|url| url.trim_end_matches('/').to_string()).unwrap_or_else(|_| "https://sessionserver.mojang.com".into())
+}
+
+// Largest profile picture upload accepted, in bytes.
+pub fn get_avatar_max_size() -> usize {
+    dotenvy::var("AVATAR_MAX_SIZE").ok().and_then(|size| size.parse().ok()).unwrap_or(1_000_000)
+}
+
+// The UUID an offline mode server derives from a player name, Java's
+// `UUID.nameUUIDFromBytes("OfflinePlayer:" + name)`.
+pub fn offline_uuid(name: &str) -> String {
+    let mut bytes: [u8; 16] = Md5::digest(format!("OfflinePlayer:{}", name).as_bytes()).into();
+    bytes[6] = (bytes[6] & 0x0f) | 0x30;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    uuid::Uuid::from_bytes(bytes).to_string()
+}
+
+// Escapes user input that ends up inside a `$regex` query.
+pub fn escape_regex(input: &str) -> String {
+    let mut escaped = String::with_capacity(input.len());
+    for c in input.chars() {
+        if "\\.+*?()|[]{}^$".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
 pub fn get_session_token<'a>(req: &'a HttpRequest) -> Option<&'a str> {
     return req.headers().get("x-session")?.to_str().ok();
 }
@@ -21,3 +145,65 @@ pub fn verified_csrf<'a>(req: &'a HttpRequest) -> bool {
         return false;
     }
 }
+
+// Resolves the account behind a state changing request, checking the CSRF token
+// and the session header. The error is the response to send back as is.
+pub async fn authenticate(client: &Client, req: &HttpRequest) -> Result<Accounts, HttpResponse> {
+    if !verified_csrf(req) {
+        return Err(HttpResponse::Unauthorized().json(json!({ "status": 401, "success": false, "error": "Invalid CSRF Token!" })));
+    }
+    let account = session_account(client, req).await?;
+    if account.suspended {
+        return Err(HttpResponse::Forbidden().json(json!({ "status": 403, "success": false, "error": "This account is suspended." })));
+    }
+    Ok(account)
+}
+
+// Same as `authenticate` for requests that only read, where no CSRF token is sent.
+// Suspended accounts pass, they keep read access.
+pub async fn session_account(client: &Client, req: &HttpRequest) -> Result<Accounts, HttpResponse> {
+    let Some(token) = get_session_token(req) else {
+        return Err(HttpResponse::Unauthorized().json(json!({ "status": 401, "success": false, "error": "Could not authenticate request." })));
+    };
+    let accounts: Collection<Accounts> = client.database("ouja_skins").collection("accounts");
+    match accounts.find_one(doc! { "session": token }, None).await {
+        Ok(Some(account)) => Ok(account),
+        Ok(None) => Err(HttpResponse::Unauthorized().json(json!({ "status": 401, "success": false, "error": "Could not authenticate request." }))),
+        Err(err) => Err(HttpResponse::InternalServerError().json(json!({ "status": 500, "success": false, "error": err.to_string() }))),
+    }
+}
+
+// A unique index rejected the write, used to make inserts idempotent.
+pub fn is_duplicate_key(err: &mongodb::error::Error) -> bool {
+    matches!(&*err.kind, ErrorKind::Write(WriteFailure::WriteError(error)) if error.code == 11000)
+}
+
+// Maps account ids to their usernames, accounts that no longer exist are left out.
+pub async fn find_usernames(client: &Client, ids: &[&String]) -> mongodb::error::Result<HashMap<String, String>> {
+    let accounts: Collection<Accounts> = client.database("ouja_skins").collection("accounts");
+    let mut cursor = accounts.find(doc! { "id": { "$in": ids } }, None).await?;
+    let mut usernames = HashMap::new();
+    while let Some(account) = cursor.next().await {
+        let account = account?;
+        usernames.insert(account.id, account.username);
+    }
+    Ok(usernames)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_the_uuid_offline_servers_give() {
+        assert_eq!(offline_uuid("Notch"), "b50ad385-829d-3141-a216-7e7d7539ba7f");
+    }
+
+    #[test]
+    fn offline_uuids_are_version_3_and_case_sensitive() {
+        let uuid = uuid::Uuid::parse_str(&offline_uuid("jeb_")).unwrap();
+        assert_eq!(uuid.get_version_num(), 3);
+        assert_eq!(uuid.get_variant(), uuid::Variant::RFC4122);
+        assert_ne!(offline_uuid("notch"), offline_uuid("Notch"));
+    }
+}